@@ -1,17 +1,16 @@
 extern crate clover;
 extern crate env_logger;
 
-use std::sync::{Arc, Mutex};
-
 fn main() {
     env_logger::init().unwrap();
 
-    // Wrap the client in `Arc` and `Mutex`. Use the same client for any and
-    // all threads you use (It will automatically throttle as per the API
-    // rules). Also, it wraps around a `reqwest` client which also recommends
-    // to use the same client for all requests.
-    let client = Arc::new(Mutex::new(clover::Client::new().unwrap()));
-    let g = clover::Board::new(client, "g").unwrap();
+    // Use the same client for any and all boards you use (It will
+    // automatically throttle as per the API rules). Clones are cheap and
+    // share the same throttling, so hand each board its own clone. Also, it
+    // wraps around a `reqwest` client which also recommends to use the same
+    // client for all requests.
+    let client = clover::Client::new().unwrap();
+    let g = clover::Board::new(client.clone(), "g").unwrap();
 
     // Initialize the cache by sweeping the board in one request. The catalog
     // struct is not too useful, but the option is there to use it. Run
@@ -24,9 +23,9 @@ fn main() {
 
     // Some time passes. Want to update threads again. Note that each thread
     // is throttled to only be able to send update requests every 10 seconds.
-    // This is the absolute minimum required by the API. This is done by
-    // sleeping the thread until the time is up, so don't call it at intervals
-    // any less than 10 seconds.
+    // This is the absolute minimum required by the API. The client's request
+    // scheduler holds the request back until the time is up, so `update`
+    // blocks if you call it at intervals any less than 10 seconds.
     for mut candidate in &mut sticky_candidates {
         candidate.update().expect("Failed to update");
     }
@@ -1,33 +1,45 @@
-use std::io::Read;
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
-use regex::RegexBuilder;
-use chrono::{DateTime, UTC};
+use regex::{Regex, RegexBuilder};
+use futures::{future, Future};
 use reqwest::StatusCode;
 
+use post::default;
+
 /// A `Board` represents a 4chan board. Automatically caches threads when
 /// `catalog` is run. Using `find_cached` or `get_thread` will lazily update
 /// the requested thread(s).
-#[derive(Debug)]
+///
+/// Clones share the same client and thread cache, and the validators of
+/// the catalog and archive.
+#[derive(Clone, Debug)]
 pub struct Board {
     pub name: String,
-    pub client: Arc<Mutex<::Client>>,
+    pub info: BoardInfo,
+    pub client: ::Client,
     pub thread_cache: Arc<Mutex<::ThreadCache>>,
-    catalog_last_modified: Arc<Mutex<Option<DateTime<UTC>>>>
+    // The validators of the last 200 response from each of the board's
+    // urls requested conditionally, ie. the catalog and the archive.
+    validators: Arc<Mutex<HashMap<String, ::Validators>>>
 }
 
 impl Board {
     /// Creates a new `Board`.
-    pub fn new(client: Arc<Mutex<::Client>>, name: &str) -> ::Result<Board> {
-        if !client.lock().unwrap().is_valid_board(name) {
-            return Err(::Error::InvalidBoardName)
-        }
+    pub fn new(client: ::Client, name: &str) -> ::Result<Board> {
+        try!(client.load_boards());
+        let info = match client.board_info(name) {
+            Some(info) => info,
+            None => return Err(::Error::InvalidBoardName)
+        };
 
         Ok(Board {
             client: client,
             name: name.to_string(),
+            info: info,
             thread_cache: Arc::new(Mutex::new(::ThreadCache::new())),
-            catalog_last_modified: Arc::new(Mutex::new(None))
+            validators: Arc::new(Mutex::new(HashMap::new()))
         })
     }
 
@@ -35,45 +47,41 @@ impl Board {
     /// thread cache. Returns `Some<Catalog>` if the catalog was updated,
     /// and `None` if the catalog was not modified since the last request.
     pub fn catalog(&self) -> ::Result<Option<Catalog>> {
-        let mut res = match *self.catalog_last_modified.lock().unwrap() {
-            None => {
-                try!(self.client.lock().unwrap().get(
-                        &format!("https://a.4cdn.org/{}/catalog.json",
-                                 self.name),
-                        None))
-            },
-            Some(dt) => {
-                // If-Modified-Since: Sat, 29 Oct 1994 19:43:31 GMT
-                //                    %a,  %d %b  %Y   %T       GMT
-                let format = "%a, %d %b %Y %T GMT";
-                let fmt_date = dt.format(&format).to_string();
-                try!(self.client.lock().unwrap().get(
-                        &format!("https://a.4cdn.org/{}/catalog.json",
-                                 self.name),
-                        Some(::IfModifiedSince(fmt_date))))
-            }
-        };
+        let url = self.api_url("catalog.json");
+        let res = try!(self.get_conditional(&url).wait());
+        self.apply_catalog(res)
+    }
+
+    /// Like `catalog`, but returns a future instead of blocking.
+    pub fn catalog_async(&self) -> ::ApiFuture<Option<Catalog>> {
+        let url = self.api_url("catalog.json");
+        let board = self.clone();
+        Box::new(self.get_conditional(&url)
+                 .and_then(move |res| board.apply_catalog(res)))
+    }
 
-        match *res.status() {
+    fn apply_catalog(&self, res: ::Response) -> ::Result<Option<Catalog>> {
+        let url = self.api_url("catalog.json");
+        match res.status {
             StatusCode::Ok => {
-                *self.catalog_last_modified.lock().unwrap() = Some(UTC::now());
-                let mut buf = String::new();
-                try!(res.read_to_string(&mut buf));
-                let corrected = r#"{"pages":"#.to_string() + &buf + "}";
-                let catalog: Catalog = try!(::serde_json::from_str(&corrected));
-
-                for topic in catalog.topics() {
-                    self.thread_cache.lock().unwrap()
-                        .insert(::Thread::from_topic(topic.clone(),
-                        &self.name, self.client.clone()));
-                }
+                let catalog = Catalog {
+                    pages: try!(::error::from_json(&url, &res.body))
+                };
+                self.record_validators(&url, &res);
+
+                self.with_cache(|cache| {
+                    for topic in catalog.topics() {
+                        cache.insert(::Thread::from_topic(topic.clone(),
+                            &self.name, self.client.clone()));
+                    }
+                });
 
                 Ok(Some(catalog))
             },
             StatusCode::NotModified => {
                 Ok(None)
             },
-            _ => Err(::Error::UnexpectedResponse)
+            _ => Err(::Error::from_response(&url, &res))
         }
     }
 
@@ -83,28 +91,24 @@ impl Board {
     ///
     /// The threads are updated before they are returned. Automatically
     /// excludes expired threads.
+    ///
+    /// Only cached threads are searched, not those kept by the cache's
+    /// `Storage`; query the storage itself for those.
     pub fn find_cached(&self, query: &str) -> ::Result<Vec<::Thread>> {
-        let mut regex_builder = RegexBuilder::new(query);
-        let regex = try!(regex_builder
-                         .case_insensitive(true)
-                         .unicode(true)
-                         .build());
-
-        let mut threads = self.thread_cache.lock().unwrap().threads
-            .values()
-            .filter(|&t| t.is_match(&regex))
-            .cloned()
-            .collect::<Vec<::Thread>>();
+        let regex = try!(search_regex(query));
 
         // TODO: A returned thread is cloned twice. Needs refactoring.
-        let mut return_threads = Vec::new();
-        for mut thread in &mut threads {
+        let mut threads = Vec::new();
+        for mut thread in self.cached_matches(&regex) {
             try!(thread.update());
-            if !thread.expired {
-                return_threads.push(thread.clone());
-            } else {
-                // Update cache, removing expired threads
-                self.thread_cache.lock().unwrap().remove(thread.topic.no);
+            self.with_cache(|cache| {
+                cache.replace(thread.clone());
+                if thread.is_expired() {
+                    cache.retire(thread.topic.no);
+                }
+            });
+            if !thread.is_expired() {
+                threads.push(thread);
             }
         }
 
@@ -114,30 +118,357 @@ impl Board {
     /// Get a `Thread` that you know the thread number of. First checks that
     /// the thread is in the cache, and updates it if it is. If not, then
     /// makes a request, adds the created struct to the cache, and returns
-    /// the thread.
+    /// the thread. A thread kept by the cache's `Storage` is restored into
+    /// the cache and updated instead, unless it expired or was archived.
     pub fn get_thread(& self, thread_no: u64) -> ::Result<::Thread> {
-        if self.thread_cache.lock().unwrap().contains(thread_no) {
-            try!(self.thread_cache.lock().unwrap().threads
-                .get_mut(&thread_no)
-                .unwrap()
-                .update());
-            return Ok(self.thread_cache.lock().unwrap()
-                      .get(thread_no).unwrap().clone())
-        }
-
-        let mut res = try!(self.client.lock().unwrap().get(
-                &format!("https://a.4cdn.org/{}/thread/{}.json",
-                         self.name, thread_no), None));
-        let mut buf = String::new();
-        try!(res.read_to_string(&mut buf));
+        if !self.thread_cache.lock().unwrap().contains(thread_no) {
+            if let Some(thread) = try!(self.stored_thread(thread_no)) {
+                self.with_cache(|cache| cache.insert(thread));
+            }
+        }
+
+        // The cached thread is updated from a copy, without holding the
+        // cache's lock while waiting on the network.
+        let cached = self.thread_cache.lock().unwrap().get(thread_no).cloned();
+        if let Some(mut thread) = cached {
+            try!(thread.update());
+            self.with_cache(|cache| cache.replace(thread.clone()));
+            return Ok(thread)
+        }
+
+        let url = self.api_url(&format!("thread/{}.json", thread_no));
+        let res = try!(self.fetch(::Request::new(&url)));
+        self.apply_new_thread(&url, res)
+    }
+
+    /// Like `get_thread`, but returns a future instead of blocking.
+    ///
+    /// A cached thread is updated from a copy, which replaces the cached
+    /// thread once the update is done.
+    pub fn get_thread_async(&self, thread_no: u64) -> ::ApiFuture<::Thread> {
+        if !self.thread_cache.lock().unwrap().contains(thread_no) {
+            match self.stored_thread(thread_no) {
+                Ok(Some(thread)) => {
+                    self.with_cache(|cache| cache.insert(thread));
+                },
+                Ok(None) => {},
+                Err(e) => return Box::new(future::err(e))
+            }
+        }
+
+        let cached = self.thread_cache.lock().unwrap().get(thread_no).cloned();
+        let board = self.clone();
+
+        match cached {
+            Some(thread) => {
+                Box::new(thread.update_async().map(move |(thread, _)| {
+                    board.with_cache(|cache| cache.replace(thread.clone()));
+                    thread
+                }))
+            },
+            None => {
+                let url = self.api_url(&format!("thread/{}.json", thread_no));
+                let ticket = self.client.enqueue(::Request::new(&url));
+                Box::new(ticket.and_then(move |res| {
+                    board.apply_new_thread(&url, res)
+                }))
+            }
+        }
+    }
+
+    /// Like `find_cached`, but returns a future instead of blocking. The
+    /// matching threads are updated concurrently, as fast as the client's
+    /// scheduler allows.
+    pub fn find_cached_async(&self, query: &str)
+        -> ::ApiFuture<Vec<::Thread>> {
+        let regex = match search_regex(query) {
+            Ok(regex) => regex,
+            Err(e) => return Box::new(future::err(e))
+        };
+
+        let updates = self.cached_matches(&regex).into_iter()
+            .map(|thread| thread.update_async())
+            .collect::<Vec<_>>();
+        let board = self.clone();
+
+        Box::new(future::join_all(updates).map(move |updated| {
+            board.with_cache(|cache| {
+                for &(ref thread, _) in &updated {
+                    cache.replace(thread.clone());
+                    if thread.is_expired() {
+                        cache.retire(thread.topic.no);
+                    }
+                }
+            });
+            let mut threads = Vec::new();
+            for (thread, _) in updated {
+                if !thread.is_expired() {
+                    threads.push(thread);
+                }
+            }
+            threads
+        }))
+    }
+
+    /// Adds a thread fetched by `get_thread` to the cache, and stores it.
+    fn apply_new_thread(&self, url: &str, res: ::Response)
+        -> ::Result<::Thread> {
+        if res.status != StatusCode::Ok {
+            return Err(::Error::from_response(url, &res))
+        }
+
         let deserializer: ::ThreadDeserializer = try!(
-            ::serde_json::from_str(&buf));
-        let thread = ::Thread::from_deserializer(
-            deserializer, &self.name, self.client.clone());
-        self.thread_cache.lock().unwrap().insert(thread.clone());
+            ::error::from_json(url, &res.body));
+        let mut thread = try!(::Thread::from_deserializer(
+            deserializer, &self.name, self.client.clone()));
+        let storage = self.thread_cache.lock().unwrap().storage();
+        if let Some(storage) = storage {
+            store_thread(&*storage, &thread);
+            thread.set_storage(storage);
+        }
+        self.with_cache(|cache| cache.insert(thread.clone()));
+
+        Ok(thread)
+    }
+
+    /// The cached threads matching `regex`.
+    fn cached_matches(&self, regex: &Regex) -> Vec<::Thread> {
+        self.thread_cache.lock().unwrap().threads
+            .values()
+            .filter(|&t| t.is_match(regex))
+            .cloned()
+            .collect()
+    }
+
+    /// Get the numbers of a board's archived threads from archive.json.
+    /// Returns `Some` if the archive was updated, and `None` if it was not
+    /// modified since the last request. Only boards with an archive (see
+    /// `BoardInfo::has_archive`) have one.
+    ///
+    /// Fetch an archived thread with `get_archived_thread`.
+    pub fn archive(&self) -> ::Result<Option<Vec<u64>>> {
+        let url = self.api_url("archive.json");
+        let res = try!(self.get_conditional(&url).wait());
+
+        match res.status {
+            StatusCode::Ok => {
+                let archive = try!(::error::from_json(&url, &res.body));
+                self.record_validators(&url, &res);
+                Ok(Some(archive))
+            },
+            StatusCode::NotModified => {
+                Ok(None)
+            },
+            _ => Err(::Error::from_response(&url, &res))
+        }
+    }
 
+    /// Get an archived `Thread` that you know the thread number of, eg. from
+    /// `archive`. The thread is added to the cache like with `get_thread`.
+    ///
+    /// Unlike an expired thread, which was pruned and is gone for good, an
+    /// archived thread can still be fetched but will never change again.
+    /// Returns `Error::NotArchived` if the thread isn't archived.
+    pub fn get_archived_thread(&self, thread_no: u64) -> ::Result<::Thread> {
+        let thread = try!(self.get_thread(thread_no));
+        if !thread.is_archived() {
+            return Err(::Error::NotArchived(thread_no))
+        }
         Ok(thread)
     }
+
+    /// Get an index page of the board, counting from 1 up to `info.pages`.
+    /// Each thread on the page is previewed by its topic and latest few
+    /// replies, which are merged into the thread cache (see
+    /// `Thread::merge_preview`).
+    pub fn page(&self, page: u8) -> ::Result<IndexPage> {
+        let url = self.api_url(&format!("{}.json", page));
+        let res = try!(self.fetch(::Request::new(&url)));
+
+        match res.status {
+            StatusCode::Ok => {
+                let index_page: IndexPage = try!(
+                    ::error::from_json(&url, &res.body));
+
+                try!(self.with_cache(|cache| -> ::Result<()> {
+                    for preview in index_page.threads.iter()
+                        .filter(|t| !t.posts.is_empty()) {
+                        let thread_no = preview.posts[0].no;
+                        if let Some(mut thread) =
+                            cache.get(thread_no).cloned() {
+                            thread.merge_preview(&preview.posts);
+                            cache.replace(thread);
+                            continue
+                        }
+                        cache.insert(try!(::Thread::from_preview(
+                            preview.clone(), &self.name,
+                            self.client.clone())));
+                    }
+                    Ok(())
+                }));
+
+                Ok(index_page)
+            },
+            _ => Err(::Error::from_response(&url, &res))
+        }
+    }
+
+    /// Get a board's current `ThreadList` from threads.json. This is a much
+    /// lighter request than `catalog`, listing only each thread's number,
+    /// page, reply count and time of last modification.
+    pub fn thread_list(&self) -> ::Result<ThreadList> {
+        let url = self.api_url("threads.json");
+        let res = try!(self.fetch(::Request::new(&url)));
+
+        match res.status {
+            StatusCode::Ok => {
+                Ok(ThreadList {
+                    pages: try!(::error::from_json(&url, &res.body))
+                })
+            },
+            _ => Err(::Error::from_response(&url, &res))
+        }
+    }
+
+    /// Updates only the cached threads that changed, using the `ThreadList`
+    /// to decide which need it. A thread needs an update if it was modified
+    /// after its cached topic, or if it is no longer listed (so that it gets
+    /// marked as expired or archived). Expired threads are retired from the
+    /// cache (see `ThreadCache::retire`), archived threads are kept but no
+    /// longer updated.
+    ///
+    /// The threads are updated from copies, without holding the cache's lock,
+    /// and each replaces the cached thread once it is updated.
+    ///
+    /// Returns the numbers of the threads that were updated.
+    pub fn refresh_cache(&self) -> ::Result<Vec<u64>> {
+        let thread_list = try!(self.thread_list());
+
+        let stale = self.thread_cache.lock().unwrap().threads
+            .values()
+            .filter(|t| !t.is_expired() && !t.is_archived())
+            .filter(|t| match thread_list.find(t.topic.no) {
+                Some(entry) => entry.last_modified > t.topic.last_modified,
+                None => true
+            })
+            .cloned()
+            .collect::<Vec<::Thread>>();
+
+        let mut updated = Vec::new();
+        for mut thread in stale {
+            try!(thread.update());
+            let thread_no = thread.topic.no;
+            self.with_cache(|cache| {
+                cache.replace(thread.clone());
+                if thread.is_expired() {
+                    cache.retire(thread_no);
+                }
+            });
+            updated.push(thread_no);
+        }
+
+        Ok(updated)
+    }
+
+    /// Saves the board's thread cache to `path`, along with the validators
+    /// of its catalog and archive. See `ThreadCache::save`.
+    pub fn save_cache<P: AsRef<Path>>(&self, path: P) -> ::Result<()> {
+        let validators = self.validators.lock().unwrap().clone()
+            .into_iter()
+            .collect();
+        self.thread_cache.lock().unwrap().save_with(path, validators)
+    }
+
+    /// Loads a thread cache saved with `save_cache` into the board's cache,
+    /// and the validators of its catalog and archive. Returns the number of
+    /// threads loaded.
+    pub fn load_cache<P: AsRef<Path>>(&self, path: P) -> ::Result<usize> {
+        let (loaded, validators) = try!(self.with_cache(|cache| {
+            cache.load_with(path, &self.client)
+        }));
+        self.validators.lock().unwrap().extend(validators);
+        Ok(loaded)
+    }
+
+    /// A thread kept by the cache's `Storage`, if it has one.
+    fn stored_thread(&self, thread_no: u64) -> ::Result<Option<::Thread>> {
+        let storage = match self.thread_cache.lock().unwrap().storage() {
+            Some(storage) => storage,
+            None => return Ok(None)
+        };
+        let stored = try!(storage.load_thread(&self.name, thread_no));
+        Ok(stored.map(|s| ::Thread::from_stored(s, self.client.clone())))
+    }
+
+    /// Runs `f` on the locked thread cache, then stores the threads it
+    /// evicted once the lock is released.
+    ///
+    /// A thread that was never updated holds only its topic or a preview,
+    /// so it isn't stored over a copy the storage already has.
+    fn with_cache<T, F>(&self, f: F) -> T
+        where F: FnOnce(&mut ::ThreadCache) -> T {
+        let (result, storage, evicted) = {
+            let mut cache = self.thread_cache.lock().unwrap();
+            let result = f(&mut *cache);
+            (result, cache.storage(), cache.take_evicted())
+        };
+        if let Some(storage) = storage {
+            for thread in &evicted {
+                if thread.last_updated().is_none() {
+                    match storage.load_thread(&self.name, thread.topic.no) {
+                        Ok(None) => {},
+                        _ => continue
+                    }
+                }
+                store_thread(&*storage, thread);
+            }
+        }
+        result
+    }
+
+    /// Enqueues a GET request to the url, conditional on the validators of
+    /// the last 200 response from it.
+    fn get_conditional(&self, url: &str) -> ::Ticket {
+        let validators = self.validators.lock().unwrap().get(url).cloned();
+        self.client.enqueue(::Request::new(url)
+                            .conditional(validators.as_ref()))
+    }
+
+    /// Records the validators of a 200 response from the url.
+    fn record_validators(&self, url: &str, res: &::Response) {
+        let mut validators = self.validators.lock().unwrap();
+        match ::Validators::from_response(res) {
+            Some(v) => { validators.insert(url.to_string(), v); },
+            None => { validators.remove(url); }
+        }
+    }
+
+    /// Enqueues a request with the client and waits for the response.
+    fn fetch(&self, request: ::Request) -> ::Result<::Response> {
+        self.client.enqueue(request).wait()
+    }
+
+    /// The url of an API endpoint of this board, eg. `api_url("catalog.json")`
+    /// for the catalog.
+    fn api_url(&self, path: &str) -> String {
+        format!("{}/{}/{}", self.client.api_url(), self.name, path)
+    }
+}
+
+/// Stores a thread, only logging a failure: the cache is still up to date.
+fn store_thread(storage: &::Storage, thread: &::Thread) {
+    if let Err(e) = storage.store_thread(thread) {
+        warn!("Failed to store thread {}: {}", thread.topic.no, e);
+    }
+}
+
+/// Builds the case insensitive, unicode regex used to search the cache.
+fn search_regex(query: &str) -> ::Result<Regex> {
+    let mut regex_builder = RegexBuilder::new(query);
+    Ok(try!(regex_builder
+            .case_insensitive(true)
+            .unicode(true)
+            .build()))
 }
 
 /// A `Catalog` contains the information from the 4chan catalog API. Rather
@@ -180,9 +511,368 @@ impl Catalog {
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Page {
-    page: u8,
+    pub page: u8,
     // Rather than `Thread` objects, pages create a `Post` representing the
     // thread's topic (aka. OP).
     #[serde(rename="threads")]
     pub topics: Vec<::Post>
 }
+
+/// An `IndexPage` is one page of a board's index. Each thread is previewed by
+/// its topic, which has `omitted_posts` and `omitted_images` set, followed by
+/// its latest few replies.
+#[derive(Clone, Debug, Deserialize)]
+pub struct IndexPage {
+    pub threads: Vec<::ThreadDeserializer>
+}
+
+/// A `ThreadList` is the content of a board's threads.json: every thread on
+/// the board, page by page, in bump order.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ThreadList {
+    pub pages: Vec<ThreadListPage>
+}
+
+impl ThreadList {
+    pub fn entries(&self) -> Vec<&ThreadListEntry> {
+        self.pages.iter()
+            .fold(Vec::new(), |mut entries, p| {
+                entries.extend(&p.threads);
+                entries
+            })
+    }
+
+    /// Finds the entry of a thread, if it is listed.
+    pub fn find(&self, thread_no: u64) -> Option<&ThreadListEntry> {
+        self.pages.iter()
+            .flat_map(|p| p.threads.iter())
+            .find(|e| e.no == thread_no)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ThreadListPage {
+    pub page: u8,
+    pub threads: Vec<ThreadListEntry>
+}
+
+/// A thread as listed by threads.json.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ThreadListEntry {
+    pub no: u64,
+    pub last_modified: i64,
+    #[serde(default="default::<u32>")]
+    pub replies: u32
+}
+
+/// A `BoardInfo` holds everything boards.json says about a board.
+///
+/// Read more about the boards.json at https://github.com/4chan/4chan-API.
+/// Defaults are for optional fields.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct BoardInfo {
+    pub board: String,
+    pub title: String,
+    pub ws_board: u8,
+    #[serde(default="default::<u8>")]
+    pub per_page: u8,
+    #[serde(default="default::<u8>")]
+    pub pages: u8,
+    #[serde(default="default::<u32>")]
+    pub max_filesize: u32,
+    #[serde(default="default::<u32>")]
+    pub max_webm_filesize: u32,
+    #[serde(default="default::<u32>")]
+    pub max_comment_chars: u32,
+    #[serde(default="default::<u32>")]
+    pub max_webm_duration: u32,
+    #[serde(default="default::<u32>")]
+    pub bump_limit: u32,
+    #[serde(default="default::<u32>")]
+    pub image_limit: u32,
+    #[serde(default="default::<Cooldowns>")]
+    pub cooldowns: Cooldowns,
+    #[serde(default="default::<String>")]
+    pub meta_description: String,
+    #[serde(default="default::<u8>")]
+    pub is_archived: u8,
+    #[serde(default="default::<u8>")]
+    pub spoilers: u8,
+    #[serde(default="default::<u8>")]
+    pub custom_spoilers: u8,
+    #[serde(default="default::<u8>")]
+    pub forced_anon: u8,
+    #[serde(default="default::<u8>")]
+    pub user_ids: u8,
+    #[serde(default="default::<u8>")]
+    pub country_flags: u8,
+    #[serde(default="default::<HashMap<String, String>>")]
+    pub board_flags: HashMap<String, String>,
+    #[serde(default="default::<u8>")]
+    pub code_tags: u8,
+    #[serde(default="default::<u8>")]
+    pub math_tags: u8,
+    #[serde(default="default::<u8>")]
+    pub sjis_tags: u8,
+    #[serde(default="default::<u8>")]
+    pub oekaki: u8,
+    #[serde(default="default::<u8>")]
+    pub text_only: u8,
+    #[serde(default="default::<u8>")]
+    pub require_subject: u8,
+    #[serde(default="default::<u8>")]
+    pub webm_audio: u8,
+    #[serde(default="default::<u16>")]
+    pub min_image_width: u16,
+    #[serde(default="default::<u16>")]
+    pub min_image_height: u16
+}
+
+impl BoardInfo {
+    /// Whether the board is a blue (work safe) board.
+    pub fn is_sfw(&self) -> bool {
+        self.ws_board == 1
+    }
+
+    /// Whether the board keeps an archive of its pruned threads.
+    pub fn has_archive(&self) -> bool {
+        self.is_archived == 1
+    }
+}
+
+/// The minimum number of seconds between posts of each kind.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Cooldowns {
+    #[serde(default="default::<u32>")]
+    pub threads: u32,
+    #[serde(default="default::<u32>")]
+    pub replies: u32,
+    #[serde(default="default::<u32>")]
+    pub images: u32
+}
+
+/// The boards.json response.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BoardList {
+    pub boards: Vec<BoardInfo>
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+    use reqwest::StatusCode;
+
+    use testing;
+
+    #[test]
+    fn catalog_populates_cache() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+
+        let catalog = g.catalog().unwrap().expect("catalog was modified");
+        assert_eq!(2, catalog.topics().len());
+        assert!(g.thread_cache.lock().unwrap().contains(100));
+        assert!(g.thread_cache.lock().unwrap().contains(200));
+
+        transport.insert(testing::CATALOG_URL,
+                         ::Response::new(StatusCode::NotModified, ""));
+        assert!(g.catalog().unwrap().is_none());
+    }
+
+    #[test]
+    fn boards_keep_their_own_validators() {
+        let transport = testing::transport();
+        let mut res = ::Response::new(StatusCode::NotFound, "");
+        res.headers.set_raw("Last-Modified",
+                            vec![b"Sat, 15 Apr 2017 01:03:25 GMT".to_vec()]);
+        transport.insert(testing::CATALOG_URL, res.clone());
+        let client = testing::client(&transport);
+        let a = ::Board::new(client.clone(), "g").unwrap();
+        let b = ::Board::new(client, "g").unwrap();
+        assert!(b.catalog().is_err());
+
+        // Only 200 responses are revalidated, and only by the board that
+        // got them.
+        res.status = StatusCode::Ok;
+        res.body = testing::CATALOG.to_string();
+        transport.insert(testing::CATALOG_URL, res);
+        assert!(a.catalog().unwrap().is_some());
+        assert!(b.catalog().unwrap().is_some());
+        assert!(b.thread_cache.lock().unwrap().contains(100));
+        a.catalog().unwrap();
+
+        let sent = transport.requests().into_iter()
+            .filter(|&(ref url, _)| url == testing::CATALOG_URL)
+            .map(|(_, headers)| headers.has::<::IfModifiedSince>())
+            .collect::<Vec<bool>>();
+        assert_eq!(vec![false, false, false, true], sent);
+    }
+
+    #[test]
+    fn find_cached_updates_threads() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let _ = g.catalog().unwrap();
+
+        let threads = g.find_cached("gentoo").unwrap();
+        assert_eq!(1, threads.len());
+        assert_eq!(100, threads[0].topic.no);
+        assert_eq!(1, threads[0].replies.len());
+        assert_eq!(101, threads[0].replies[0].no);
+        assert_eq!(1, g.thread_cache.lock().unwrap()
+                   .get(100).unwrap().replies.len());
+    }
+
+    #[test]
+    fn find_cached_excludes_expired_threads() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let _ = g.catalog().unwrap();
+
+        // Thread 200 is gone, so it is expired and dropped from the cache.
+        assert!(g.find_cached("desktop").unwrap().is_empty());
+        assert!(!g.thread_cache.lock().unwrap().contains(200));
+        assert!(g.thread_cache.lock().unwrap().contains(100));
+    }
+
+    #[test]
+    fn async_board_api() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+
+        let catalog = g.catalog_async().wait().unwrap();
+        assert_eq!(2, catalog.expect("catalog was modified").topics().len());
+
+        let threads = g.find_cached_async("gentoo").wait().unwrap();
+        assert_eq!(1, threads.len());
+        assert_eq!(1, threads[0].replies.len());
+        assert_eq!(1, g.thread_cache.lock().unwrap()
+                   .get(100).unwrap().replies.len());
+
+        // Thread 200 is gone, so it is expired and dropped from the cache.
+        assert!(g.get_thread_async(200).wait().unwrap().is_expired());
+        assert!(g.find_cached_async("desktop").wait().unwrap().is_empty());
+        assert!(!g.thread_cache.lock().unwrap().contains(200));
+    }
+
+    #[test]
+    fn refresh_cache_updates_modified_threads() {
+        let transport = testing::transport();
+        transport.insert_ok("https://a.4cdn.org/g/threads.json", r#"[
+            {"page": 1, "threads": [
+                {"no": 100, "last_modified": 1492218300, "replies": 1},
+                {"no": 200, "last_modified": 1492218205, "replies": 0}
+            ]}
+        ]"#);
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let _ = g.catalog().unwrap();
+
+        let thread_list = g.thread_list().unwrap();
+        assert_eq!(2, thread_list.entries().len());
+        assert_eq!(1, thread_list.find(100).unwrap().replies);
+
+        assert_eq!(vec![100], g.refresh_cache().unwrap());
+        assert!(!transport.requests().iter()
+                .any(|&(ref url, _)| url.ends_with("/thread/200.json")));
+        let cache = g.thread_cache.lock().unwrap();
+        assert_eq!(1, cache.get(100).unwrap().replies.len());
+        assert!(cache.contains(200));
+    }
+
+    #[test]
+    fn archive_and_archived_threads() {
+        let transport = testing::transport();
+        transport.insert_ok("https://a.4cdn.org/g/archive.json", "[90, 91]");
+        transport.insert_ok("https://a.4cdn.org/g/thread/90.json",
+                            r#"{"posts": [
+            {"no": 90, "resto": 0, "now": "04/14/17(Fri)01:03:25",
+             "time": 1492131805, "archived": 1, "archived_on": 1492218205,
+             "closed": 1}
+        ]}"#);
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+
+        assert_eq!(Some(vec![90, 91]), g.archive().unwrap());
+        transport.insert("https://a.4cdn.org/g/archive.json",
+                         ::Response::new(StatusCode::NotModified, ""));
+        assert_eq!(None, g.archive().unwrap());
+
+        let thread = g.get_archived_thread(90).unwrap();
+        assert!(thread.is_archived());
+        assert!(!thread.is_expired());
+        assert!(!thread.wants_update());
+        assert_eq!(1492218205, thread.archived_on().unwrap().timestamp());
+        assert!(g.get_archived_thread(100).is_err());
+    }
+
+    #[test]
+    fn page_previews_fill_cache() {
+        let transport = testing::transport();
+        transport.insert_ok("https://a.4cdn.org/g/1.json", r#"{"threads": [
+            {"posts": [
+                {"no": 100, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+                 "time": 1492218205, "replies": 1, "omitted_posts": 0},
+                {"no": 101, "resto": 100, "now": "04/15/17(Sat)01:05:00",
+                 "time": 1492218300}
+            ]},
+            {"posts": [
+                {"no": 300, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+                 "time": 1492218205, "replies": 7, "omitted_posts": 6,
+                 "last_modified": 1492218400},
+                {"no": 307, "resto": 300, "now": "04/15/17(Sat)01:06:40",
+                 "time": 1492218400}
+            ]}
+        ]}"#);
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let _ = g.catalog().unwrap();
+
+        let page = g.page(1).unwrap();
+        assert_eq!(2, page.threads.len());
+
+        let cache = g.thread_cache.lock().unwrap();
+        let complete = cache.get(100).unwrap();
+        assert_eq!(1, complete.replies.len());
+        assert_eq!(101, complete.replies[0].no);
+
+        let partial = cache.get(300).unwrap();
+        assert!(partial.replies.is_empty());
+        assert_eq!(307, partial.last_reply().unwrap().no);
+        assert_eq!(6, partial.topic.omitted_posts);
+    }
+
+    #[test]
+    fn board_info_from_boards_json() {
+        let transport = ::MemoryTransport::new();
+        transport.insert_ok(testing::BOARDS_URL, r#"{"boards": [
+            {"board": "g", "title": "Technology", "ws_board": 1,
+             "per_page": 15, "pages": 10, "bump_limit": 310,
+             "image_limit": 150, "code_tags": 1,
+             "cooldowns": {"threads": 600, "replies": 60, "images": 60}}
+        ]}"#);
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+
+        assert_eq!(310, g.info.bump_limit);
+        assert_eq!(60, g.info.cooldowns.replies);
+        assert_eq!(1, g.info.code_tags);
+        assert_eq!(0, g.info.math_tags);
+        assert!(g.info.is_sfw());
+    }
+
+    #[test]
+    fn missing_thread_is_not_found() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+
+        match g.get_thread(999) {
+            Err(::Error::NotFound { url }) => {
+                assert_eq!("https://a.4cdn.org/g/thread/999.json", url);
+            },
+            res => panic!("unexpected result: {:?}", res)
+        }
+    }
+
+    #[test]
+    fn invalid_board_name() {
+        let transport = testing::transport();
+        assert!(::Board::new(testing::client(&transport), "z").is_err());
+    }
+}
@@ -1,117 +1,357 @@
-use std::io::Read;
-use std::thread::sleep;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time;
 
-use chrono::{DateTime, Duration, UTC};
-use serde_json::Value;
-use reqwest::header::{Headers, UserAgent};
+use futures::Future;
+use reqwest::StatusCode;
+use reqwest::header::UserAgent;
 
-static BOARDS_URL: &'static str = "https://a.4cdn.org/boards.json";
+/// The default base url of the JSON API.
+pub static API_URL: &'static str = "https://a.4cdn.org";
+/// The default base url of the image servers.
+pub static IMAGE_URL: &'static str = "https://i.4cdn.org";
+/// The default base url of the website.
+pub static WEB_URL: &'static str = "https://boards.4chan.org";
+/// The default user agent sent with every request.
+pub static USER_AGENT: &'static str = "clover-rs";
 
 /// A `Client` makes all the API GET requests. All requests are throttled by
 /// a 1 second interval to comply with the 4chan API rules. Use the same client
 /// for all your boards (see examples).
-#[derive(Debug)]
+///
+/// A `Client` is a cheap handle: clones share the same request scheduler and
+/// board list, so hand a clone to every `Board`. Requests are made by the
+/// `Scheduler` in the background, without holding any lock while waiting on
+/// the network. Use `enqueue` to make a request without blocking.
+#[derive(Clone, Debug)]
 pub struct Client {
-    reqwest_client: ::reqwest::Client,
-    all_boards: Vec<String>,
-    // List of blue boards
-    sfw_boards: Vec<String>,
-    // List of red boards
-    nsfw_boards: Vec<String>,
-    last_request: DateTime<UTC>,
+    inner: Arc<Inner>
+}
+
+#[derive(Debug)]
+struct Inner {
+    scheduler: ::Scheduler,
+    api_url: String,
+    image_url: String,
+    web_url: String,
+    user_agent: String,
+    thread_interval: time::Duration,
+    // None until the board list is loaded or supplied.
+    boards: RwLock<Option<Vec<::BoardInfo>>>,
+    // The validators of the last 200 response from boards.json.
+    boards_validators: Mutex<Option<::Validators>>
 }
 
 impl Client {
-    /// Creates a new `Client`.
+    /// Creates a new `Client` that makes its requests over the network.
+    /// Use `ClientBuilder` to configure it.
     pub fn new() -> ::Result<Client> {
-        let client = try!(::reqwest::Client::new());
-
-        let last_request = UTC::now();
-        let mut res = try!(client.get(BOARDS_URL).send());
-        assert!(res.status().is_success());
-
-        let mut buf = String::new();
-        try!(res.read_to_string(&mut buf));
-
-        let v: Value = try!(::serde_json::from_str(&buf));
-        let mut sfw_boards = Vec::new();
-        let mut nsfw_boards = Vec::new();
-        let mut all_boards = Vec::new();
-
-        let boards = v["boards"].as_array().unwrap();
-        for board in boards {
-            let name = board["board"].as_str().unwrap();
-            if board["ws_board"].as_u64().unwrap() == 1 {
-                sfw_boards.push(name.to_string());
-            } else{
-                nsfw_boards.push(name.to_string());
-            }
-            all_boards.push(name.to_string());
-        }
+        ClientBuilder::new().build()
+    }
 
-        Ok(Client{
-            reqwest_client: client,
-            all_boards: all_boards,
-            sfw_boards: sfw_boards,
-            nsfw_boards: nsfw_boards,
-            last_request: last_request,
-        })
+    /// Creates a new `Client` that makes its requests through `transport`.
+    pub fn with_transport(transport: Box<::Transport>) -> ::Result<Client> {
+        ClientBuilder::new().transport(transport).build()
     }
 
-    /// Makes a GET request to the url. Adds an "If-Modified-Since" header if
-    /// provided.
-    pub fn get(&mut self, url: &str, headers: Option<::IfModifiedSince>)
-        -> ::Result<::reqwest::Response> {
-        // Throttle so that we make no more than 1 request per second.
-        let diff = UTC::now().signed_duration_since(self.last_request);
-        if diff < Duration::seconds(1) {
-            sleep(try!(diff.to_std()));
+    /// Fetches the board list from boards.json if it hasn't been loaded or
+    /// supplied yet. `Board::new` calls this for you.
+    pub fn load_boards(&self) -> ::Result<()> {
+        if self.inner.boards.read().unwrap().is_some() { return Ok(()) }
+        self.reload_boards()
+    }
+
+    /// Fetches the board list from boards.json again, if it changed since it
+    /// was last fetched.
+    pub fn reload_boards(&self) -> ::Result<()> {
+        let url = format!("{}/boards.json", self.inner.api_url);
+        let validators = self.inner.boards_validators.lock().unwrap().clone();
+        let res = try!(self.enqueue(::Request::new(&url)
+                                    .conditional(validators.as_ref()))
+                       .wait());
+        match res.status {
+            StatusCode::Ok => {
+                let list: ::BoardList = try!(
+                    ::error::from_json(&url, &res.body));
+                *self.inner.boards.write().unwrap() = Some(list.boards);
+                *self.inner.boards_validators.lock().unwrap() =
+                    ::Validators::from_response(&res);
+                Ok(())
+            },
+            StatusCode::NotModified => Ok(()),
+            _ => Err(::Error::from_response(&url, &res))
         }
+    }
 
-        let mut req_headers = Headers::new();
-        req_headers.set(UserAgent("clover-rs".to_string()));
-        if headers.is_some() {
-            for header in headers {
-                req_headers.set(header);
-            }
+    /// Makes a GET request to the url. Adds an "If-Modified-Since" header if
+    /// provided. Blocks until the request has been made.
+    pub fn get(&self, url: &str, headers: Option<::IfModifiedSince>)
+        -> ::Result<::Response> {
+        self.get_async(url, headers).wait()
+    }
+
+    /// Like `get`, but returns the `Ticket` for the request, which is a
+    /// future resolving to the response, instead of blocking.
+    pub fn get_async(&self, url: &str, headers: Option<::IfModifiedSince>)
+        -> ::Ticket {
+        let mut request = ::Request::new(url);
+        if let Some(header) = headers {
+            request.headers.set(header);
         }
+        self.enqueue(request)
+    }
 
-        debug!("[{:?}] Making request to url: {} with headers: {:?}",
-               UTC::now(), url, req_headers);
+    /// Adds a request to the scheduler's queue without blocking. Wait on the
+    /// returned `Ticket` for the response.
+    pub fn enqueue(&self, mut request: ::Request) -> ::Ticket {
+        request.headers.set(UserAgent(self.inner.user_agent.clone()));
+        self.inner.scheduler.enqueue(request)
+    }
 
-        let res = try!(self.reqwest_client.get(url)
-                           .headers(req_headers)
-                           .send());
+    /// The minimum interval between two updates of the same thread.
+    pub fn thread_interval(&self) -> time::Duration {
+        self.inner.thread_interval
+    }
 
-        self.last_request = UTC::now();
+    /// The number of requests waiting to be made.
+    pub fn queue_depth(&self) -> usize {
+        self.inner.scheduler.queue_depth()
+    }
 
-        Ok(res)
+    /// The average time requests waited before being made.
+    pub fn average_wait(&self) -> time::Duration {
+        self.inner.scheduler.average_wait()
+    }
+
+    /// The base url of the JSON API, eg. "https://a.4cdn.org".
+    pub fn api_url(&self) -> &str {
+        &self.inner.api_url
+    }
+
+    /// The base url of the image servers, eg. "https://i.4cdn.org".
+    pub fn image_url(&self) -> &str {
+        &self.inner.image_url
+    }
+
+    /// The base url of the website, eg. "https://boards.4chan.org".
+    pub fn web_url(&self) -> &str {
+        &self.inner.web_url
     }
 
     pub fn is_sfw(&self, name: &str) -> bool {
-        self.sfw_boards.contains(&name.to_string())
+        self.board_info(name).map_or(false, |b| b.is_sfw())
     }
 
     pub fn is_nsfw(&self, name: &str) -> bool {
-        self.nsfw_boards.contains(&name.to_string())
+        self.board_info(name).map_or(false, |b| !b.is_sfw())
     }
 
     pub fn is_valid_board(&self, name: &str) -> bool {
-        self.all_boards.contains(&name.to_string())
+        self.board_info(name).is_some()
     }
     
     pub fn all_boards(&self) -> Vec<String> {
-        self.all_boards.clone()
+        self.boards().iter().map(|b| b.board.clone()).collect()
+    }
+
+    /// The `BoardInfo` of every board, in the order boards.json lists them.
+    /// Empty until the board list is loaded.
+    pub fn boards(&self) -> Vec<::BoardInfo> {
+        self.inner.boards.read().unwrap().clone().unwrap_or_default()
+    }
+
+    /// The `BoardInfo` of a board, or None if there is no such board.
+    pub fn board_info(&self, name: &str) -> Option<::BoardInfo> {
+        self.inner.boards.read().unwrap().as_ref()
+            .and_then(|boards| boards.iter().find(|b| b.board == name))
+            .cloned()
+    }
+}
+
+/// A `ClientBuilder` configures a `Client`. By default it talks to 4chan
+/// over the network and fetches the board list when it is built.
+#[derive(Debug)]
+pub struct ClientBuilder {
+    transport: Option<Box<::Transport>>,
+    api_url: String,
+    image_url: String,
+    web_url: String,
+    user_agent: String,
+    timeout: Option<time::Duration>,
+    request_interval: time::Duration,
+    thread_interval: time::Duration,
+    retry_policy: ::RetryPolicy,
+    // The directory and size limit in bytes of the disk cache, if any.
+    disk_cache: Option<(PathBuf, u64)>,
+    boards: Option<Vec<::BoardInfo>>,
+    lazy_boards: bool
+}
+
+impl ClientBuilder {
+    /// Creates a new `ClientBuilder` with the default settings.
+    pub fn new() -> ClientBuilder {
+        ClientBuilder {
+            transport: None,
+            api_url: API_URL.to_string(),
+            image_url: IMAGE_URL.to_string(),
+            web_url: WEB_URL.to_string(),
+            user_agent: USER_AGENT.to_string(),
+            timeout: None,
+            request_interval: time::Duration::from_millis(
+                ::scheduler::REQUEST_INTERVAL_MS),
+            thread_interval: time::Duration::from_secs(
+                ::thread::THREAD_INTERVAL_SECS),
+            retry_policy: ::RetryPolicy::default(),
+            disk_cache: None,
+            boards: None,
+            lazy_boards: false
+        }
+    }
+
+    /// Makes requests through `transport` instead of over the network. The
+    /// timeout is ignored when a transport is given.
+    pub fn transport(mut self, transport: Box<::Transport>) -> ClientBuilder {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Sets the base url of the JSON API. Defaults to `API_URL`.
+    pub fn api_url(mut self, url: &str) -> ClientBuilder {
+        self.api_url = url.trim_right_matches('/').to_string();
+        self
+    }
+
+    /// Sets the base url of the image servers. Defaults to `IMAGE_URL`.
+    pub fn image_url(mut self, url: &str) -> ClientBuilder {
+        self.image_url = url.trim_right_matches('/').to_string();
+        self
+    }
+
+    /// Sets the base url of the website. Defaults to `WEB_URL`.
+    pub fn web_url(mut self, url: &str) -> ClientBuilder {
+        self.web_url = url.trim_right_matches('/').to_string();
+        self
+    }
+
+    /// Sets the user agent sent with every request. Defaults to
+    /// `USER_AGENT`.
+    pub fn user_agent(mut self, user_agent: &str) -> ClientBuilder {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Sets the timeout of every request made over the network.
+    pub fn timeout(mut self, timeout: time::Duration) -> ClientBuilder {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the minimum interval between any two requests. Defaults to 1
+    /// second as per the API rules.
+    pub fn request_interval(mut self, interval: time::Duration)
+        -> ClientBuilder {
+        self.request_interval = interval;
+        self
+    }
+
+    /// Sets the minimum interval between two updates of the same thread.
+    /// Defaults to 10 seconds as per the API rules.
+    pub fn thread_interval(mut self, interval: time::Duration)
+        -> ClientBuilder {
+        self.thread_interval = interval;
+        self
+    }
+
+    /// Sets how failed requests are retried. Defaults to
+    /// `RetryPolicy::default()`; use `RetryPolicy::none()` to never retry.
+    pub fn retry_policy(mut self, policy: ::RetryPolicy) -> ClientBuilder {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Keeps responses in a `CachingTransport` in `dir`, at most `max_bytes`
+    /// of them, so they can be revalidated instead of downloaded again after
+    /// a restart.
+    pub fn disk_cache<P: AsRef<Path>>(mut self, dir: P, max_bytes: u64)
+        -> ClientBuilder {
+        self.disk_cache = Some((dir.as_ref().to_path_buf(), max_bytes));
+        self
+    }
+
+    /// Adds a board to a board list supplied up front. boards.json is never
+    /// requested when a board list is supplied. Every other field of the
+    /// board's `BoardInfo` is left at its default.
+    pub fn board(self, name: &str, sfw: bool) -> ClientBuilder {
+        self.board_info(::BoardInfo {
+            board: name.to_string(),
+            ws_board: if sfw { 1 } else { 0 },
+            ..Default::default()
+        })
+    }
+
+    /// Adds a board to a board list supplied up front, like `board`.
+    pub fn board_info(mut self, info: ::BoardInfo) -> ClientBuilder {
+        let mut boards = self.boards.take().unwrap_or_default();
+        boards.push(info);
+        self.boards = Some(boards);
+        self
+    }
+
+    /// Defers fetching boards.json until the first `Board` is created.
+    pub fn lazy_boards(mut self, lazy: bool) -> ClientBuilder {
+        self.lazy_boards = lazy;
+        self
+    }
+
+    /// Builds the `Client`, fetching the board list unless it was supplied
+    /// or deferred.
+    pub fn build(self) -> ::Result<Client> {
+        let mut transport = match self.transport {
+            Some(transport) => transport,
+            None => {
+                let mut transport = try!(::HttpTransport::new());
+                if let Some(timeout) = self.timeout {
+                    transport.timeout(timeout);
+                }
+                Box::new(transport)
+            }
+        };
+
+        if let Some((dir, max_bytes)) = self.disk_cache {
+            let cache = try!(::CachingTransport::new(transport, dir));
+            transport = Box::new(cache.max_bytes(max_bytes));
+        }
+
+        let load_boards = self.boards.is_none() && !self.lazy_boards;
+        let client = Client {
+            inner: Arc::new(Inner {
+                scheduler: ::Scheduler::with_retry_policy(
+                    transport, self.request_interval, self.retry_policy),
+                api_url: self.api_url,
+                image_url: self.image_url,
+                web_url: self.web_url,
+                user_agent: self.user_agent,
+                thread_interval: self.thread_interval,
+                boards: RwLock::new(self.boards),
+                boards_validators: Mutex::new(None)
+            })
+        };
+
+        if load_boards {
+            try!(client.load_boards());
+        }
+
+        Ok(client)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::sync::{Arc, Mutex};
-
+    // Hits 4chan live, run it with `cargo test -- --ignored`.
     #[test]
+    #[ignore]
     fn get_board() {
-        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let client = ::Client::new().unwrap();
         let g = ::Board::new(client, "g").unwrap();
         let _ = g.catalog().unwrap();
         assert!(g.thread_cache.lock().unwrap().threads.len() > 0);
@@ -119,5 +359,63 @@ mod test {
             .expect("Found no matches for installgentoo");
         assert!(sticky_candidates.len() > 0);
     }
-}
 
+    #[test]
+    fn builder_supplied_boards() {
+        let transport = ::MemoryTransport::new();
+        let client = ::ClientBuilder::new()
+            .transport(Box::new(transport.clone()))
+            .api_url("http://localhost:8080/")
+            .board("g", true)
+            .board("b", false)
+            .build()
+            .unwrap();
+
+        assert!(transport.requests().is_empty());
+        assert_eq!("http://localhost:8080", client.api_url());
+        assert!(client.is_sfw("g"));
+        assert!(client.is_nsfw("b"));
+    }
+
+    #[test]
+    fn builder_lazy_boards() {
+        let transport = ::testing::transport();
+        let client = ::ClientBuilder::new()
+            .transport(Box::new(transport.clone()))
+            .lazy_boards(true)
+            .build()
+            .unwrap();
+
+        assert!(!client.is_valid_board("g"));
+        client.load_boards().unwrap();
+        client.load_boards().unwrap();
+        assert!(client.is_valid_board("g"));
+        assert_eq!(1, transport.requests().len());
+        assert_eq!("Technology", client.board_info("g").unwrap().title);
+        assert_eq!(vec!["g".to_string(), "b".to_string()],
+                   client.all_boards());
+    }
+
+    #[test]
+    fn clones_share_state() {
+        let transport = ::testing::transport();
+        let client = ::ClientBuilder::new()
+            .transport(Box::new(transport.clone()))
+            .lazy_boards(true)
+            .build()
+            .unwrap();
+
+        let clone = client.clone();
+        clone.load_boards().unwrap();
+        assert!(client.is_valid_board("g"));
+        client.load_boards().unwrap();
+        assert_eq!(1, transport.requests().len());
+    }
+
+    #[test]
+    fn bad_boards_response_is_an_error() {
+        let transport = ::MemoryTransport::new();
+        transport.insert_ok(::testing::BOARDS_URL, "{}");
+        assert!(::Client::with_transport(Box::new(transport)).is_err());
+    }
+}
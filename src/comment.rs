@@ -0,0 +1,613 @@
+use std::char;
+
+/// A `Comment` is the comment of a `Post` parsed from its HTML, see
+/// `Post::comment`.
+///
+/// The parser never fails: unknown tags are dropped but their contents
+/// kept, unclosed tags are closed at the end, stray closing tags and stray
+/// "<" are kept as text, and unknown HTML entities are left as they are.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Comment {
+    pub nodes: Vec<Node>
+}
+
+/// A `Node` of a `Comment`. Nodes that can be nested hold more nodes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    // Text, with HTML entities decoded.
+    Text(String),
+    LineBreak,
+    // A line starting with ">", including the ">".
+    Greentext(Vec<Node>),
+    // A link to a post, thread or board, and its text, eg. ">>123".
+    Quote { target: QuoteTarget, text: String },
+    // A link to a post that no longer exists, and its number if the text
+    // ends with one.
+    DeadLink { post_no: Option<u64>, text: String },
+    Spoiler(Vec<Node>),
+    // A [code] block, with its line breaks as newlines.
+    Code(String),
+    // Shift JIS art from a [sjis] block.
+    Sjis(String),
+    // The TeX source of a [math] tag, or of an [eqn] tag if `display`.
+    Math { source: String, display: bool },
+    Bold(Vec<Node>),
+    RedText(Vec<Node>),
+    // The message a moderator added to the post, eg. "(USER WAS BANNED FOR
+    // THIS POST)".
+    Banned(String)
+}
+
+/// What a `Node::Quote` links to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QuoteTarget {
+    // A post in the same thread.
+    Post(u64),
+    // A post in another thread, on `board` if the link names one. Links to
+    // a whole thread have its number as `post_no`.
+    Thread { board: Option<String>, thread_no: u64, post_no: u64 },
+    // A board, or a search of its catalog.
+    Board { board: String, search: Option<String> }
+}
+
+impl Comment {
+    /// Parses the HTML of a comment.
+    pub fn parse(html: &str) -> Comment {
+        Comment { nodes: convert(parse_html(html)) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Renders the comment as plain text, Markdown or ANSI colored text.
+    pub fn render(&self, format: ::Format) -> String {
+        ::render::comment(self, format)
+    }
+
+    /// Every quote link in the comment, in order.
+    pub fn quotes(&self) -> Vec<&QuoteTarget> {
+        let mut quotes = Vec::new();
+        collect_quotes(&self.nodes, &mut quotes);
+        quotes
+    }
+}
+
+fn collect_quotes<'a>(nodes: &'a [Node], quotes: &mut Vec<&'a QuoteTarget>) {
+    for node in nodes {
+        match *node {
+            Node::Quote { ref target, .. } => quotes.push(target),
+            Node::Greentext(ref nodes) |
+            Node::Spoiler(ref nodes) |
+            Node::Bold(ref nodes) |
+            Node::RedText(ref nodes) => collect_quotes(nodes, quotes),
+            _ => {}
+        }
+    }
+}
+
+/// An HTML element, before it is turned into `Node`s.
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Content>
+}
+
+enum Content {
+    Element(Element),
+    Text(String),
+    Break
+}
+
+enum Tag {
+    Open { name: String, attrs: Vec<(String, String)> },
+    Close(String),
+    // A comment or doctype.
+    Skip
+}
+
+/// Elements that never have contents or a closing tag.
+static VOID_ELEMENTS: &'static [&'static str] = &[
+    "br", "wbr", "hr", "img", "input", "meta", "link"
+];
+
+impl Element {
+    fn new(name: String, attrs: Vec<(String, String)>) -> Element {
+        Element { name: name, attrs: attrs, children: Vec::new() }
+    }
+
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter()
+            .find(|&&(ref attr, _)| attr == name)
+            .map(|&(_, ref value)| &value[..])
+    }
+
+    fn has_class(&self, class: &str) -> bool {
+        self.attr("class")
+            .map_or(false, |c| c.split_whitespace().any(|c| c == class))
+    }
+
+    /// Whether the element is styled or colored red.
+    fn is_red(&self) -> bool {
+        let style = self.attr("style").unwrap_or("").to_lowercase()
+            .replace(' ', "");
+        let color = self.attr("color").unwrap_or("").to_lowercase();
+        ["color:red", "color:#f00", "color:#ff0000"].iter()
+            .any(|red| style.contains(red)) ||
+            color == "red" || color == "#f00" || color == "#ff0000"
+    }
+
+    /// The text of the element, with line breaks as newlines.
+    fn text(&self) -> String {
+        let mut text = String::new();
+        push_text(&self.children, &mut text);
+        text
+    }
+}
+
+fn push_text(contents: &[Content], text: &mut String) {
+    for content in contents {
+        match *content {
+            Content::Element(ref element) => {
+                push_text(&element.children, text)
+            },
+            Content::Text(ref t) => text.push_str(t),
+            Content::Break => text.push('\n')
+        }
+    }
+}
+
+/// Parses HTML into a tree of `Content`, as tolerantly as browsers do.
+fn parse_html(html: &str) -> Vec<Content> {
+    let mut stack = vec![Element::new(String::new(), Vec::new())];
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let lt = rest.find('<').unwrap_or(rest.len());
+        if lt > 0 {
+            let text = decode_entities(&rest[..lt]);
+            stack.last_mut().unwrap().children.push(Content::Text(text));
+            rest = &rest[lt..];
+            continue
+        }
+
+        let (tag, len) = match parse_tag(rest) {
+            Some(tag) => tag,
+            None => {
+                let text = "<".to_string();
+                stack.last_mut().unwrap().children.push(Content::Text(text));
+                rest = &rest[1..];
+                continue
+            }
+        };
+        rest = &rest[len..];
+
+        match tag {
+            Tag::Open { ref name, .. } if name == "br" => {
+                stack.last_mut().unwrap().children.push(Content::Break);
+            },
+            Tag::Open { ref name, .. }
+                if VOID_ELEMENTS.iter().any(|&void| *name == void) => {},
+            Tag::Open { name, attrs } => {
+                stack.push(Element::new(name, attrs));
+            },
+            Tag::Close(name) => {
+                // Close everything up to the element, if it is open at all.
+                if stack.iter().skip(1).any(|e| e.name == name) {
+                    loop {
+                        let closed = stack.last().unwrap().name == name;
+                        close_element(&mut stack);
+                        if closed { break }
+                    }
+                }
+            },
+            Tag::Skip => {}
+        }
+    }
+
+    while stack.len() > 1 {
+        close_element(&mut stack);
+    }
+    stack.pop().unwrap().children
+}
+
+/// Pops the innermost open element into its parent.
+fn close_element(stack: &mut Vec<Element>) {
+    let element = stack.pop().unwrap();
+    stack.last_mut().unwrap().children.push(Content::Element(element));
+}
+
+/// Parses the tag at the start of `html`. Returns the tag and its length,
+/// or None if the "<" doesn't start a tag.
+fn parse_tag(html: &str) -> Option<(Tag, usize)> {
+    if html.starts_with("<!") {
+        let end = if html.starts_with("<!--") { "-->" } else { ">" };
+        let len = html.find(end).map_or(html.len(), |i| i + end.len());
+        return Some((Tag::Skip, len))
+    }
+
+    let mut quote = None;
+    let mut end = None;
+    for (i, c) in html.char_indices().skip(1) {
+        match quote {
+            Some(q) => if c == q { quote = None },
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '>' => {
+                    end = Some(i);
+                    break
+                },
+                '<' => return None,
+                _ => {}
+            }
+        }
+    }
+    let end = match end {
+        Some(end) => end,
+        None => return None
+    };
+
+    let inner = &html[1..end];
+    let (closing, inner) = if inner.starts_with('/') {
+        (true, &inner[1..])
+    } else {
+        (false, inner)
+    };
+    if !inner.starts_with(|c: char| c.is_alphabetic()) {
+        return None
+    }
+    let name_len = inner.find(|c: char| !c.is_alphanumeric())
+        .unwrap_or(inner.len());
+    let name = inner[..name_len].to_lowercase();
+
+    let tag = if closing {
+        Tag::Close(name)
+    } else {
+        Tag::Open { name: name, attrs: parse_attrs(&inner[name_len..]) }
+    };
+    Some((tag, end + 1))
+}
+
+/// Parses the attributes of a tag, quoted, unquoted or without a value.
+fn parse_attrs(attrs: &str) -> Vec<(String, String)> {
+    let mut parsed = Vec::new();
+    let mut rest = attrs.trim_left();
+
+    while !rest.is_empty() {
+        let name_len = rest
+            .find(|c: char| c == '=' || c == '/' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let name = rest[..name_len].to_lowercase();
+        rest = rest[name_len..].trim_left();
+
+        let mut value = String::new();
+        if rest.starts_with('=') {
+            rest = rest[1..].trim_left();
+            if rest.starts_with('"') || rest.starts_with('\'') {
+                let quoted = &rest[1..];
+                let end = quoted.find(&rest[..1]).unwrap_or(quoted.len());
+                value = decode_entities(&quoted[..end]);
+                rest = if end < quoted.len() { &quoted[end + 1..] } else { "" };
+            } else {
+                let end = rest.find(char::is_whitespace)
+                    .unwrap_or(rest.len());
+                value = decode_entities(&rest[..end]);
+                rest = &rest[end..];
+            }
+        } else if name_len == 0 {
+            // A stray "/", eg. in "<br/>".
+            rest = &rest[1..];
+        }
+
+        if !name.is_empty() {
+            parsed.push((name, value));
+        }
+        rest = rest.trim_left();
+    }
+
+    parsed
+}
+
+/// Decodes named and numeric HTML entities. Unknown entities are kept.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        let entity = match rest.find(';') {
+            Some(semi) if semi <= 10 => {
+                decode_entity(&rest[1..semi]).map(|c| (c, semi + 1))
+            },
+            _ => None
+        };
+        match entity {
+            Some((c, len)) => {
+                decoded.push(c);
+                rest = &rest[len..];
+            },
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+            u32::from_str_radix(&entity[2..], 16).ok()
+                .and_then(char::from_u32)
+        },
+        _ if entity.starts_with('#') => {
+            entity[1..].parse().ok().and_then(char::from_u32)
+        },
+        _ => None
+    }
+}
+
+/// Turns parsed HTML into `Node`s.
+fn convert(contents: Vec<Content>) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    for content in contents {
+        match content {
+            Content::Element(element) => {
+                for node in convert_element(element) {
+                    push_node(&mut nodes, node);
+                }
+            },
+            Content::Text(text) => push_node(&mut nodes, Node::Text(text)),
+            Content::Break => nodes.push(Node::LineBreak)
+        }
+    }
+
+    nodes.into_iter()
+        .flat_map(|node| match node {
+            Node::Text(text) => split_math(&text),
+            node => vec![node]
+        })
+        .collect()
+}
+
+/// Pushes a node, merging it into the last one if both are text.
+fn push_node(nodes: &mut Vec<Node>, node: Node) {
+    if let Node::Text(ref text) = node {
+        if let Some(&mut Node::Text(ref mut last)) = nodes.last_mut() {
+            last.push_str(text);
+            return
+        }
+    }
+    nodes.push(node);
+}
+
+fn convert_element(element: Element) -> Vec<Node> {
+    let is_red = element.is_red();
+    let node = match &element.name[..] {
+        "a" if element.has_class("quotelink") => {
+            let target = element.attr("href").and_then(parse_href);
+            match target {
+                Some(target) => {
+                    Node::Quote { target: target, text: element.text() }
+                },
+                None => return convert(element.children)
+            }
+        },
+        "span" if element.has_class("quote") => {
+            Node::Greentext(convert(element.children))
+        },
+        "span" if element.has_class("deadlink") => {
+            let text = element.text();
+            let post_no = text.rsplit(|c: char| !c.is_digit(10)).next()
+                .and_then(|no| no.parse().ok());
+            Node::DeadLink { post_no: post_no, text: text }
+        },
+        "span" if element.has_class("sjis") => Node::Sjis(element.text()),
+        "span" if element.has_class("spoiler") => {
+            Node::Spoiler(convert(element.children))
+        },
+        "s" => Node::Spoiler(convert(element.children)),
+        "pre" => Node::Code(element.text()),
+        "b" | "strong" | "span" | "font" if is_red => {
+            let text = element.text();
+            if text.trim().starts_with('(') &&
+                text.trim().ends_with("FOR THIS POST)") {
+                Node::Banned(text.trim().to_string())
+            } else {
+                Node::RedText(convert(element.children))
+            }
+        },
+        "b" | "strong" => Node::Bold(convert(element.children)),
+        // Anything else, eg. plain links, only keeps its contents.
+        _ => return convert(element.children)
+    };
+    vec![node]
+}
+
+/// Splits [math] and [eqn] tags out of text.
+fn split_math(text: &str) -> Vec<Node> {
+    let tags = [("[math]", "[/math]", false), ("[eqn]", "[/eqn]", true)];
+    let mut nodes = Vec::new();
+    let mut rest = text;
+
+    loop {
+        let next = tags.iter()
+            .filter_map(|&(open, close, display)| {
+                rest.find(open).map(|start| (start, open, close, display))
+            })
+            .min_by_key(|&(start, _, _, _)| start);
+        let (start, open, close, display) = match next {
+            Some(next) => next,
+            None => break
+        };
+        let source_start = start + open.len();
+        let end = match rest[source_start..].find(close) {
+            Some(end) => source_start + end,
+            None => break
+        };
+
+        if start > 0 {
+            nodes.push(Node::Text(rest[..start].to_string()));
+        }
+        nodes.push(Node::Math {
+            source: rest[source_start..end].to_string(),
+            display: display
+        });
+        rest = &rest[end + close.len()..];
+    }
+
+    if !rest.is_empty() {
+        nodes.push(Node::Text(rest.to_string()));
+    }
+    nodes
+}
+
+/// Parses the href of a quote link, eg. "#p123", "/g/thread/100#p123" or
+/// "//boards.4chan.org/g/catalog#s=rust".
+fn parse_href(href: &str) -> Option<QuoteTarget> {
+    if href.starts_with("#p") {
+        return href[2..].parse().ok().map(QuoteTarget::Post)
+    }
+
+    // Drop the scheme and host of absolute links.
+    let path = if let Some(i) = href.find("://") {
+        let rest = &href[i + 3..];
+        rest.find('/').map_or("", |i| &rest[i..])
+    } else if href.starts_with("//") {
+        let rest = &href[2..];
+        rest.find('/').map_or("", |i| &rest[i..])
+    } else {
+        href
+    };
+
+    let (path, fragment) = match path.find('#') {
+        Some(i) => (&path[..i], Some(&path[i + 1..])),
+        None => (path, None)
+    };
+    let post_no: Option<u64> = fragment
+        .and_then(|f| if f.starts_with('p') { f[1..].parse().ok() } else {
+            None
+        });
+    let parts = path.split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>();
+
+    if parts.len() >= 3 && parts[1] == "thread" {
+        parts[2].parse().ok().map(|thread_no| QuoteTarget::Thread {
+            board: Some(parts[0].to_string()),
+            thread_no: thread_no,
+            post_no: post_no.unwrap_or(thread_no)
+        })
+    } else if parts.len() == 1 && post_no.is_some() {
+        // An old style link to another thread on the same board.
+        parts[0].parse().ok().map(|thread_no| QuoteTarget::Thread {
+            board: None,
+            thread_no: thread_no,
+            post_no: post_no.unwrap()
+        })
+    } else if parts.len() == 1 {
+        Some(QuoteTarget::Board {
+            board: parts[0].to_string(),
+            search: None
+        })
+    } else if parts.len() == 2 && parts[1] == "catalog" {
+        Some(QuoteTarget::Board {
+            board: parts[0].to_string(),
+            search: fragment
+                .and_then(|f| if f.starts_with("s=") { Some(&f[2..]) } else {
+                    None
+                })
+                .map(|search| search.replace('+', " "))
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Node, QuoteTarget};
+
+    fn text(text: &str) -> Node {
+        Node::Text(text.to_string())
+    }
+
+    #[test]
+    fn parses_4chan_markup() {
+        let comment = ::Comment::parse(
+            "<a href=\"#p101\" class=\"quotelink\">&gt;&gt;101</a><br>\
+             <span class=\"quote\">&gt;implying</span><br>\
+             <s>spoiled <b>bold</b></s><br>\
+             <pre class=\"prettyprint\">x &amp;&amp; y<br>  z</pre>\
+             <span class=\"deadlink\">&gt;&gt;99</span> [math]x^2[/math]\
+             <br><br><strong style=\"color: red;\">\
+             (USER WAS BANNED FOR THIS POST)</strong>");
+
+        assert_eq!(vec![
+            Node::Quote {
+                target: QuoteTarget::Post(101),
+                text: ">>101".to_string()
+            },
+            Node::LineBreak,
+            Node::Greentext(vec![text(">implying")]),
+            Node::LineBreak,
+            Node::Spoiler(vec![text("spoiled "),
+                               Node::Bold(vec![text("bold")])]),
+            Node::LineBreak,
+            Node::Code("x && y\n  z".to_string()),
+            Node::DeadLink { post_no: Some(99), text: ">>99".to_string() },
+            text(" "),
+            Node::Math { source: "x^2".to_string(), display: false },
+            Node::LineBreak,
+            Node::LineBreak,
+            Node::Banned("(USER WAS BANNED FOR THIS POST)".to_string())
+        ], comment.nodes);
+        assert_eq!(vec![&QuoteTarget::Post(101)], comment.quotes());
+    }
+
+    #[test]
+    fn parses_quote_targets() {
+        let target = |href: &str| super::parse_href(href);
+        assert_eq!(Some(QuoteTarget::Thread {
+            board: Some("a".to_string()),
+            thread_no: 100,
+            post_no: 123
+        }), target("/a/thread/100#p123"));
+        assert_eq!(Some(QuoteTarget::Thread {
+            board: None,
+            thread_no: 100,
+            post_no: 123
+        }), target("100#p123"));
+        assert_eq!(Some(QuoteTarget::Board {
+            board: "g".to_string(),
+            search: None
+        }), target("//boards.4chan.org/g/"));
+        assert_eq!(Some(QuoteTarget::Board {
+            board: "g".to_string(),
+            search: Some("rust general".to_string())
+        }), target("https://boards.4chan.org/g/catalog#s=rust+general"));
+        assert_eq!(None, target("#q1"));
+    }
+
+    #[test]
+    fn tolerates_broken_html() {
+        let comment = ::Comment::parse(
+            "<b>unclosed <i>x</b> y</i> &amp a<3 &bogus; &#x41;&#66;<");
+        assert_eq!(vec![
+            Node::Bold(vec![text("unclosed x")]),
+            text(" y &amp a<3 &bogus; AB<")
+        ], comment.nodes);
+        assert!(::Comment::parse("").is_empty());
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::UTC;
+use reqwest::StatusCode;
+use reqwest::header::Headers;
+
+use transport::{header_pairs, Exchange};
+
+/// The default maximum size of the cached response bodies, 64 MiB.
+pub const DISK_CACHE_MAX_BYTES: u64 = 64 * 1024 * 1024;
+/// The default maximum number of cached responses.
+pub const DISK_CACHE_MAX_ENTRIES: usize = 10000;
+
+static INDEX_FILE: &'static str = "index.json";
+
+/// A `Transport` that keeps every 200 OK response it gets on disk, so that
+/// they survive restarts.
+///
+/// A request without any conditional headers for a cached url is sent with
+/// the cached response's validators. If the server answers 304 Not Modified,
+/// the cached response is returned instead, as if the server had sent it
+/// again. Requests that already have conditional headers get the 304 as is.
+///
+/// When the cache grows past its limits, the least recently used responses
+/// are evicted.
+///
+/// The index of the cached responses is only written when responses are
+/// evicted and when the transport is dropped, so a crash forgets the
+/// responses cached since then.
+#[derive(Debug)]
+pub struct CachingTransport {
+    inner: Box<::Transport>,
+    dir: PathBuf,
+    max_bytes: u64,
+    max_entries: usize,
+    index: Mutex<Index>
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    next_id: u64,
+    // Incremented on every use of an entry, to order them by recency.
+    clock: u64,
+    // The total size of the entries, kept up to date by `insert` and
+    // `remove`. Summed up again when the index is read.
+    #[serde(skip_serializing, skip_deserializing)]
+    size: u64
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Entry {
+    file: String,
+    size: u64,
+    last_used: u64,
+    last_modified: Option<String>,
+    etag: Option<String>
+}
+
+impl Index {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Adds the entry for `url`, replacing any it had.
+    fn insert(&mut self, url: &str, entry: Entry) {
+        self.size += entry.size;
+        if let Some(old) = self.entries.insert(url.to_string(), entry) {
+            self.size -= old.size;
+        }
+    }
+
+    fn remove(&mut self, url: &str) -> Option<Entry> {
+        let entry = self.entries.remove(url);
+        if let Some(ref entry) = entry {
+            self.size -= entry.size;
+        }
+        entry
+    }
+
+    /// Marks the entry for `url` as just used.
+    fn touch(&mut self, url: &str) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.entries.get_mut(url) {
+            entry.last_used = clock;
+        }
+    }
+
+    /// The url of the least recently used entry.
+    fn least_recently_used(&self) -> Option<String> {
+        self.entries.iter()
+            .min_by_key(|&(_, e)| e.last_used)
+            .map(|(url, _)| url.clone())
+    }
+}
+
+impl CachingTransport {
+    /// Creates a new `CachingTransport` keeping its responses in `dir`,
+    /// creating it if it doesn't exist. Responses cached there by an earlier
+    /// run are reused.
+    pub fn new<P: AsRef<Path>>(inner: Box<::Transport>, dir: P)
+        -> ::Result<CachingTransport> {
+        try!(fs::create_dir_all(dir.as_ref()));
+        let dir = dir.as_ref().to_path_buf();
+
+        let index = match read_index(&dir.join(INDEX_FILE)) {
+            Ok(index) => index,
+            Err(e) => {
+                warn!("Ignoring unreadable disk cache index in {:?}: {}",
+                      dir, e);
+                Index::default()
+            }
+        };
+
+        Ok(CachingTransport {
+            inner: inner,
+            dir: dir,
+            max_bytes: DISK_CACHE_MAX_BYTES,
+            max_entries: DISK_CACHE_MAX_ENTRIES,
+            index: Mutex::new(index)
+        })
+    }
+
+    /// Sets the maximum total size of the cached response bodies. Defaults to
+    /// `DISK_CACHE_MAX_BYTES`.
+    pub fn max_bytes(mut self, bytes: u64) -> CachingTransport {
+        self.max_bytes = bytes;
+        self
+    }
+
+    /// Sets the maximum number of cached responses. Defaults to
+    /// `DISK_CACHE_MAX_ENTRIES`.
+    pub fn max_entries(mut self, entries: usize) -> CachingTransport {
+        self.max_entries = entries;
+        self
+    }
+
+    /// The number of cached responses.
+    pub fn len(&self) -> usize {
+        self.index.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The total size of the cached response bodies in bytes.
+    pub fn size(&self) -> u64 {
+        self.index.lock().unwrap().size()
+    }
+
+    /// Whether a response from `url` is cached.
+    pub fn contains(&self, url: &str) -> bool {
+        self.index.lock().unwrap().entries.contains_key(url)
+    }
+
+    /// Reads the cached response from `url`.
+    fn load(&self, url: &str) -> Option<::Response> {
+        let file = match self.index.lock().unwrap().entries.get(url) {
+            Some(entry) => entry.file.clone(),
+            None => return None
+        };
+
+        let mut buf = String::new();
+        let read = File::open(self.dir.join(&file))
+            .and_then(|mut f| f.read_to_string(&mut buf));
+        let exchange = read.ok()
+            .and_then(|_| ::serde_json::from_str::<Exchange>(&buf).ok());
+
+        match exchange {
+            Some(exchange) => {
+                self.index.lock().unwrap().touch(url);
+                Some(exchange.response())
+            },
+            None => {
+                warn!("Dropping unreadable cached response for {}", url);
+                self.remove(url);
+                None
+            }
+        }
+    }
+
+    /// Caches `res`, evicting the least recently used responses if the cache
+    /// grows too large. The index is written if any were.
+    fn store(&self, url: &str, res: &::Response) -> ::Result<()> {
+        let exchange = Exchange {
+            url: url.to_string(),
+            request_headers: Vec::new(),
+            status: res.status.to_u16(),
+            headers: header_pairs(&res.headers),
+            body: res.body.clone(),
+            timestamp: UTC::now().timestamp()
+        };
+
+        let mut index = self.index.lock().unwrap();
+        let existing = index.entries.get(url).map(|e| e.file.clone());
+        let file = match existing {
+            Some(file) => file,
+            None => {
+                index.next_id += 1;
+                format!("{:08}.json", index.next_id)
+            }
+        };
+
+        let mut f = try!(File::create(self.dir.join(&file)));
+        try!(f.write_all(
+            try!(::serde_json::to_string(&exchange)).as_bytes()));
+
+        index.insert(url, Entry {
+            file: file,
+            size: res.body.len() as u64,
+            last_used: 0,
+            last_modified: res.header("Last-Modified"),
+            etag: res.header("ETag")
+        });
+        index.touch(url);
+
+        let mut evicted = false;
+        while index.entries.len() > self.max_entries ||
+            index.size() > self.max_bytes {
+            let url = match index.least_recently_used() {
+                Some(url) => url,
+                None => break
+            };
+            debug!("Evicting cached response for {}", url);
+            if let Some(entry) = index.remove(&url) {
+                let _ = fs::remove_file(self.dir.join(&entry.file));
+            }
+            evicted = true;
+        }
+
+        if evicted {
+            try!(self.write_index(&index));
+        }
+        Ok(())
+    }
+
+    /// Drops the cached response from `url`.
+    fn remove(&self, url: &str) {
+        let mut index = self.index.lock().unwrap();
+        if let Some(entry) = index.remove(url) {
+            let _ = fs::remove_file(self.dir.join(&entry.file));
+            if let Err(e) = self.write_index(&index) {
+                warn!("Failed to write disk cache index: {}", e);
+            }
+        }
+    }
+
+    /// Writes the index to a temporary file first, so a crash can't leave a
+    /// half-written index behind.
+    fn write_index(&self, index: &Index) -> ::Result<()> {
+        let path = self.dir.join(INDEX_FILE);
+        let tmp = path.with_extension("tmp");
+        try!(try!(File::create(&tmp)).write_all(
+            try!(::serde_json::to_string(index)).as_bytes()));
+        try!(fs::rename(&tmp, &path));
+        Ok(())
+    }
+}
+
+impl ::Transport for CachingTransport {
+    fn get(&self, url: &str, headers: Headers) -> ::Result<::Response> {
+        let conditional = headers.has::<::IfModifiedSince>() ||
+            headers.has::<::IfNoneMatch>();
+
+        let mut sent = headers.clone();
+        if !conditional {
+            if let Some(entry) = self.index.lock().unwrap().entries.get(url) {
+                if let Some(ref last_modified) = entry.last_modified {
+                    sent.set(::IfModifiedSince(last_modified.clone()));
+                }
+                if let Some(ref etag) = entry.etag {
+                    sent.set(::IfNoneMatch(etag.clone()));
+                }
+            }
+        }
+
+        let res = try!(self.inner.get(url, sent));
+        match res.status {
+            StatusCode::Ok => {
+                if let Err(e) = self.store(url, &res) {
+                    warn!("Failed to cache response for {}: {}", url, e);
+                }
+                Ok(res)
+            },
+            StatusCode::NotModified if conditional => {
+                self.index.lock().unwrap().touch(url);
+                Ok(res)
+            },
+            StatusCode::NotModified => {
+                match self.load(url) {
+                    Some(cached) => Ok(cached),
+                    // The cached response is gone, ask for it again.
+                    None => self.inner.get(url, headers)
+                }
+            },
+            StatusCode::NotFound => {
+                self.remove(url);
+                Ok(res)
+            },
+            _ => Ok(res)
+        }
+    }
+}
+
+impl Drop for CachingTransport {
+    fn drop(&mut self) {
+        // Save the order of use.
+        let index = self.index.lock().unwrap();
+        if let Err(e) = self.write_index(&index) {
+            warn!("Failed to write disk cache index: {}", e);
+        }
+    }
+}
+
+fn read_index(path: &Path) -> ::Result<Index> {
+    if !path.exists() {
+        return Ok(Index::default())
+    }
+
+    let mut buf = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut buf));
+    let mut index: Index = try!(::serde_json::from_str(&buf));
+    index.size = index.entries.values().map(|e| e.size).sum();
+    Ok(index)
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::StatusCode;
+    use reqwest::header::Headers;
+
+    use Transport;
+    use testing;
+
+    #[test]
+    fn serves_cached_response_on_not_modified() {
+        let tmp = testing::TempDir::new("disk-cache-not-modified");
+        let dir = tmp.path();
+        let memory = ::MemoryTransport::new();
+        let mut res = ::Response::new(StatusCode::Ok, "a");
+        res.headers.set_raw("Last-Modified",
+                            vec![b"Sat, 15 Apr 2017 01:03:25 GMT".to_vec()]);
+        memory.insert("http://test/a.json", res);
+
+        {
+            let cache = ::CachingTransport::new(
+                Box::new(memory.clone()), &dir).unwrap();
+            cache.get("http://test/a.json", Headers::new()).unwrap();
+            assert!(cache.contains("http://test/a.json"));
+        }
+
+        // A new process starts with the same cache.
+        memory.insert("http://test/a.json",
+                      ::Response::new(StatusCode::NotModified, ""));
+        let cache = ::CachingTransport::new(
+            Box::new(memory.clone()), &dir).unwrap();
+
+        let res = cache.get("http://test/a.json", Headers::new()).unwrap();
+        assert_eq!(StatusCode::Ok, res.status);
+        assert_eq!("a", res.body);
+        assert_eq!(Some(&::IfModifiedSince(
+            "Sat, 15 Apr 2017 01:03:25 GMT".to_string())),
+                   memory.requests()[1].1.get::<::IfModifiedSince>());
+
+        // The caller has its own copy when it asks conditionally.
+        let mut headers = Headers::new();
+        headers.set(::IfNoneMatch("\"1\"".to_string()));
+        let res = cache.get("http://test/a.json", headers).unwrap();
+        assert_eq!(StatusCode::NotModified, res.status);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let tmp = testing::TempDir::new("disk-cache-eviction");
+        let dir = tmp.path();
+        let memory = ::MemoryTransport::new();
+        memory.insert_ok("http://test/a.json", "aaaa");
+        memory.insert_ok("http://test/b.json", "bbbb");
+        memory.insert_ok("http://test/c.json", "cccc");
+        let cache = ::CachingTransport::new(Box::new(memory), &dir).unwrap()
+            .max_bytes(8);
+
+        cache.get("http://test/a.json", Headers::new()).unwrap();
+        cache.get("http://test/b.json", Headers::new()).unwrap();
+        cache.get("http://test/a.json", Headers::new()).unwrap();
+        cache.get("http://test/c.json", Headers::new()).unwrap();
+
+        assert_eq!(2, cache.len());
+        assert_eq!(8, cache.size());
+        assert!(cache.contains("http://test/a.json"));
+        assert!(!cache.contains("http://test/b.json"));
+        // Evicting writes the index.
+        assert!(dir.join("index.json").exists());
+        assert!(!dir.join("index.tmp").exists());
+
+        cache.get("http://test/d.json", Headers::new()).unwrap();
+        assert_eq!(2, cache.len());
+
+        // The total size is summed up again from the index.
+        drop(cache);
+        let cache = ::CachingTransport::new(
+            Box::new(::MemoryTransport::new()), &dir).unwrap();
+        assert_eq!(8, cache.size());
+    }
+}
@@ -1,5 +1,11 @@
 use std::error::Error as StdError;
 use std::fmt;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+/// The number of characters of a response body kept in an `Error`.
+const BODY_SNIPPET_LEN: usize = 200;
 
 /// Any error from this crate.
 #[derive(Debug)]
@@ -18,8 +24,31 @@ pub enum Error {
     Time(::time::OutOfRangeError),
     // Tried to create a board that doesn't exist.
     InvalidBoardName,
-    // Unexpected HTTP response received.
-    UnexpectedResponse
+    // A thread without any posts, not even the topic.
+    EmptyThread,
+    // Asked for an archived thread that isn't archived.
+    NotArchived(u64),
+    // 404 Not Found.
+    NotFound { url: String },
+    // 429 Too Many Requests, with the delay asked for by "Retry-After".
+    RateLimited { url: String, retry_after: Option<Duration> },
+    // A 5xx server error, with the start of the response body.
+    ServerError { status: StatusCode, url: String, body: String },
+    // Any other unexpected HTTP response, with the start of its body.
+    UnexpectedResponse { status: StatusCode, url: String, body: String },
+    // The response is not the json we expected. `path` points to the
+    // innermost object or array the error is in, eg. "$[0].threads[3]".
+    MalformedResponse { url: String, path: String, message: String },
+    // A `ReplayTransport` got a request it has no recorded exchange for.
+    Replay(String),
+    // The `Scheduler` stopped before making the request.
+    SchedulerStopped,
+    // An error from the `rusqlite` crate.
+    #[cfg(feature = "sqlite")]
+    Sqlite(::rusqlite::Error),
+    // A saved `ThreadCache` is in a format version this version of clover
+    // can't read.
+    CacheVersion(u32)
 }
 
 impl fmt::Display for Error {
@@ -30,8 +59,38 @@ impl fmt::Display for Error {
             Error::Read(ref e) => fmt::Display::fmt(e, f),
             Error::Regex(ref e) => fmt::Display::fmt(e, f),
             Error::Time(ref e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(ref e) => fmt::Display::fmt(e, f),
             Error::InvalidBoardName => f.pad("Invalid board name"),
-            Error::UnexpectedResponse => f.pad("Unexpected HTTP response received")
+            Error::EmptyThread => f.pad("Thread has no posts"),
+            Error::NotArchived(no) => {
+                write!(f, "Thread {} is not archived", no)
+            },
+            Error::NotFound { ref url } => write!(f, "Not found: {}", url),
+            Error::RateLimited { ref url, retry_after } => {
+                try!(write!(f, "Rate limited: {}", url));
+                match retry_after {
+                    Some(delay) => write!(f, " (retry after {}s)",
+                                          delay.as_secs()),
+                    None => Ok(())
+                }
+            },
+            Error::ServerError { status, ref url, ref body } => {
+                write!(f, "Server error {} from {}: {}", status, url, body)
+            },
+            Error::UnexpectedResponse { status, ref url, ref body } => {
+                write!(f, "Unexpected HTTP response {} from {}: {}",
+                       status, url, body)
+            },
+            Error::MalformedResponse { ref url, ref path, ref message } => {
+                write!(f, "Malformed response from {} at {}: {}",
+                       url, path, message)
+            },
+            Error::Replay(ref msg) => write!(f, "Replay failed: {}", msg),
+            Error::SchedulerStopped => f.pad("Request scheduler stopped"),
+            Error::CacheVersion(version) => {
+                write!(f, "Unsupported thread cache version {}", version)
+            }
         }
     }
 }
@@ -44,8 +103,21 @@ impl StdError for Error {
             Error::Read(ref e) => e.description(),
             Error::Regex(ref e) => e.description(),
             Error::Time(ref e) => e.description(),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(ref e) => e.description(),
             Error::InvalidBoardName => "Invalid board name",
-            Error::UnexpectedResponse => "Unexpected HTTP response received"
+            Error::EmptyThread => "Thread has no posts",
+            Error::NotArchived(_) => "Thread is not archived",
+            Error::NotFound { .. } => "Not found",
+            Error::RateLimited { .. } => "Rate limited",
+            Error::ServerError { .. } => "Server error",
+            Error::UnexpectedResponse { .. } => {
+                "Unexpected HTTP response received"
+            },
+            Error::MalformedResponse { .. } => "Malformed response",
+            Error::Replay(_) => "No matching recorded exchange to replay",
+            Error::SchedulerStopped => "Request scheduler stopped",
+            Error::CacheVersion(_) => "Unsupported thread cache version"
         }
     }
 
@@ -56,10 +128,164 @@ impl StdError for Error {
             Error::Read(ref e) => Some(e),
             Error::Regex(ref e) => Some(e),
             Error::Time(ref e) => Some(e),
-            Error::InvalidBoardName => None,
-            Error::UnexpectedResponse => None
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl Error {
+    /// Creates the `Error` for an HTTP response to `url` that isn't the one
+    /// we wanted.
+    pub fn from_response(url: &str, res: &::Response) -> Error {
+        let url = url.to_string();
+        let body = res.body.chars().take(BODY_SNIPPET_LEN).collect();
+
+        match res.status {
+            StatusCode::NotFound => Error::NotFound { url: url },
+            StatusCode::TooManyRequests => Error::RateLimited {
+                url: url,
+                retry_after: res.header("Retry-After")
+                    .and_then(|value| ::retry::parse_retry_after(&value))
+            },
+            status if status.is_server_error() => Error::ServerError {
+                status: status,
+                url: url,
+                body: body
+            },
+            status => Error::UnexpectedResponse {
+                status: status,
+                url: url,
+                body: body
+            }
+        }
+    }
+
+    /// Whether making the request again might succeed: IO errors such as a
+    /// reset connection, rate limiting and server errors. Other HTTP errors,
+    /// eg. an invalid url, a TLS failure or a redirect loop, are permanent.
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            Error::Http(::reqwest::Error::Http(::hyper::Error::Io(_))) |
+            Error::Read(_) |
+            Error::RateLimited { .. } |
+            Error::ServerError { .. } => true,
+            _ => false
         }
     }
+
+    /// The HTTP status of the response that caused the error, if any.
+    pub fn status(&self) -> Option<StatusCode> {
+        match *self {
+            Error::NotFound { .. } => Some(StatusCode::NotFound),
+            Error::RateLimited { .. } => Some(StatusCode::TooManyRequests),
+            Error::ServerError { status, .. } |
+            Error::UnexpectedResponse { status, .. } => Some(status),
+            _ => None
+        }
+    }
+
+    /// The url of the request that caused the error, if any.
+    pub fn url(&self) -> Option<&str> {
+        match *self {
+            Error::NotFound { ref url } |
+            Error::RateLimited { ref url, .. } |
+            Error::ServerError { ref url, .. } |
+            Error::UnexpectedResponse { ref url, .. } |
+            Error::MalformedResponse { ref url, .. } => Some(url),
+            _ => None
+        }
+    }
+}
+
+/// Deserializes the json `body` of a response to `url`, turning any error
+/// into `Error::MalformedResponse`.
+pub fn from_json<T: ::serde::Deserialize>(url: &str, body: &str)
+    -> Result<T> {
+    ::serde_json::from_str(body).map_err(|e| Error::MalformedResponse {
+        url: url.to_string(),
+        path: json_path(body, e.line(), e.column()),
+        message: e.to_string()
+    })
+}
+
+enum Frame {
+    // The key of the current field, and whether a key is expected next.
+    Object(String, bool),
+    // The index of the current element.
+    Array(usize)
+}
+
+/// The path to the innermost object or array containing the given line and
+/// column (both starting at 1) of the json. Good enough to find the post or
+/// page that failed to deserialize.
+fn json_path(json: &str, line: usize, column: usize) -> String {
+    let mut frames = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut string = String::new();
+
+    let lines = json.lines().take(line).collect::<Vec<&str>>();
+    let last = lines.len().saturating_sub(1);
+    for (i, text) in lines.iter().enumerate() {
+        let chars = if i == last { column.saturating_sub(1) } else { !0 };
+        for c in text.chars().take(chars) {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    in_string = false;
+                    if let Some(&mut Frame::Object(ref mut key, ref mut
+                                                   wants_key)) =
+                        frames.last_mut() {
+                        if *wants_key {
+                            *key = string.clone();
+                            *wants_key = false;
+                        }
+                    }
+                } else {
+                    string.push(c);
+                }
+                continue
+            }
+
+            match c {
+                '"' => {
+                    in_string = true;
+                    string.clear();
+                },
+                '{' => frames.push(Frame::Object(String::new(), true)),
+                '[' => frames.push(Frame::Array(0)),
+                '}' | ']' => { frames.pop(); },
+                ',' => match frames.last_mut() {
+                    Some(&mut Frame::Object(_, ref mut wants_key)) => {
+                        *wants_key = true;
+                    },
+                    Some(&mut Frame::Array(ref mut index)) => *index += 1,
+                    None => {}
+                },
+                _ => {}
+            }
+        }
+    }
+
+    // Leave out the field of the innermost object.
+    let mut path = "$".to_string();
+    let depth = frames.len();
+    for (i, frame) in frames.iter().enumerate() {
+        match *frame {
+            Frame::Object(ref key, _) if i + 1 < depth => {
+                path.push('.');
+                path.push_str(key);
+            },
+            Frame::Object(..) => {},
+            Frame::Array(index) => path.push_str(&format!("[{}]", index))
+        }
+    }
+    path
 }
 
 impl From<::reqwest::Error> for Error {
@@ -92,5 +318,57 @@ impl From<::time::OutOfRangeError> for Error {
     }
 }
 
+#[cfg(feature = "sqlite")]
+impl From<::rusqlite::Error> for Error {
+    fn from(err: ::rusqlite::Error) -> Error {
+        Error::Sqlite(err)
+    }
+}
+
 /// A `Result` alias where the `Err` case is `clover::Error`
 pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+    use reqwest::StatusCode;
+
+    #[test]
+    fn errors_from_responses() {
+        let url = "https://a.4cdn.org/g/catalog.json";
+        let err = ::Error::from_response(
+            url, &::Response::new(StatusCode::NotFound, ""));
+        assert_eq!(Some(StatusCode::NotFound), err.status());
+        assert_eq!(Some(url), err.url());
+        assert!(!err.is_retryable());
+
+        let reset = ::std::io::Error::new(
+            ::std::io::ErrorKind::ConnectionReset, "reset");
+        let err = ::Error::from(
+            ::reqwest::Error::Http(::hyper::Error::Io(reset)));
+        assert!(err.is_retryable());
+        assert!(!::Error::from(::reqwest::Error::RedirectLoop).is_retryable());
+
+        let body = "x".repeat(1000);
+        let err = ::Error::from_response(
+            url, &::Response::new(StatusCode::BadGateway, &body));
+        assert!(err.is_retryable());
+        match err {
+            ::Error::ServerError { body, .. } => assert_eq!(200, body.len()),
+            err => panic!("unexpected error: {:?}", err)
+        }
+    }
+
+    #[test]
+    fn malformed_response_path() {
+        let body = r#"[{"page": 1, "threads": [
+            {"no": 100, "resto": 0, "now": "", "time": 1},
+            {"no": "oops", "resto": 0, "now": "", "time": 2}
+        ]}]"#;
+        match super::from_json::<Vec<::Page>>("url", body) {
+            Err(::Error::MalformedResponse { path, .. }) => {
+                assert_eq!("$[0].threads[1]", path);
+            },
+            res => panic!("unexpected result: {:?}", res)
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use comment::QuoteTarget;
+
+/// A `ReplyGraph` records which posts of a thread quote which, from the quote
+/// links in their comments. Every `Thread` keeps one up to date as it is
+/// updated, see `Thread::reply_graph`.
+///
+/// Quotes of posts in the same thread are resolved to their numbers, and
+/// give the quoted post a backlink. Quotes of other threads and boards are
+/// kept as they are.
+#[derive(Clone, Debug, Default)]
+pub struct ReplyGraph {
+    board_name: String,
+    thread_no: u64,
+    // Every post added to the graph.
+    posts: BTreeSet<u64>,
+    deleted: HashSet<u64>,
+    // The posts in the thread each post quotes, in the order it quotes them.
+    quotes: HashMap<u64, Vec<u64>>,
+    // The posts quoting each post, oldest first.
+    backlinks: HashMap<u64, Vec<u64>>,
+    // Each post's quotes of other threads and boards.
+    external: HashMap<u64, Vec<QuoteTarget>>
+}
+
+impl ReplyGraph {
+    /// Creates an empty `ReplyGraph` for the thread `thread_no` on a board.
+    pub fn new(board_name: &str, thread_no: u64) -> ReplyGraph {
+        ReplyGraph {
+            board_name: board_name.to_string(),
+            thread_no: thread_no,
+            ..Default::default()
+        }
+    }
+
+    /// Builds the `ReplyGraph` of every post in a thread.
+    pub fn from_thread(thread: &::Thread) -> ReplyGraph {
+        let mut graph = ReplyGraph::new(&thread.board_name, thread.topic.no);
+        graph.add_post(&thread.topic);
+        for reply in &thread.replies {
+            graph.add_post(reply);
+        }
+        graph
+    }
+
+    /// Adds a post to the graph, or replaces the quotes of a post that was
+    /// added before, eg. after its comment changed.
+    pub fn add_post(&mut self, post: &::Post) {
+        self.remove_quotes(post.no);
+        self.posts.insert(post.no);
+        if post.is_deleted() {
+            self.deleted.insert(post.no);
+        } else {
+            self.deleted.remove(&post.no);
+        }
+
+        let mut quoted = Vec::new();
+        let mut external = Vec::new();
+        for target in post.comment().quotes() {
+            match self.resolve(target) {
+                Some(no) => if !quoted.contains(&no) { quoted.push(no) },
+                None => if !external.contains(target) {
+                    external.push(target.clone())
+                }
+            }
+        }
+
+        for &no in &quoted {
+            let backlinks = self.backlinks.entry(no).or_insert_with(Vec::new);
+            if let Err(i) = backlinks.binary_search(&post.no) {
+                backlinks.insert(i, post.no);
+            }
+        }
+        if !quoted.is_empty() {
+            self.quotes.insert(post.no, quoted);
+        }
+        if !external.is_empty() {
+            self.external.insert(post.no, external);
+        }
+    }
+
+    /// Marks a post as deleted. Its quotes and backlinks are kept.
+    pub fn mark_deleted(&mut self, post_no: u64) {
+        self.deleted.insert(post_no);
+    }
+
+    /// Whether the post was added to the graph.
+    pub fn contains(&self, post_no: u64) -> bool {
+        self.posts.contains(&post_no)
+    }
+
+    /// The posts in the thread the post quotes, in the order it quotes them.
+    pub fn quotes(&self, post_no: u64) -> &[u64] {
+        self.quotes.get(&post_no).map(|quotes| &quotes[..]).unwrap_or(&[])
+    }
+
+    /// The posts quoting the post, oldest first.
+    pub fn backlinks(&self, post_no: u64) -> &[u64] {
+        self.backlinks.get(&post_no).map(|links| &links[..]).unwrap_or(&[])
+    }
+
+    /// The post's quotes of other threads and boards.
+    pub fn external(&self, post_no: u64) -> &[QuoteTarget] {
+        self.external.get(&post_no).map(|targets| &targets[..])
+            .unwrap_or(&[])
+    }
+
+    /// Every quote of another thread or board, with the number of the post
+    /// quoting it, in post order.
+    pub fn external_quotes(&self) -> Vec<(u64, &QuoteTarget)> {
+        let mut quotes = self.external.iter()
+            .flat_map(|(&no, targets)| targets.iter().map(move |t| (no, t)))
+            .collect::<Vec<(u64, &QuoteTarget)>>();
+        quotes.sort_by_key(|&(no, _)| no);
+        quotes
+    }
+
+    /// Every quote of a post that was deleted, or was gone before the thread
+    /// was fetched, as pairs of the quoting and the quoted post number.
+    pub fn orphaned_quotes(&self) -> Vec<(u64, u64)> {
+        let mut orphans = self.quotes.iter()
+            .flat_map(|(&from, quoted)| {
+                quoted.iter().map(move |&to| (from, to))
+            })
+            .filter(|&(_, to)| {
+                !self.posts.contains(&to) || self.deleted.contains(&to)
+            })
+            .collect::<Vec<(u64, u64)>>();
+        orphans.sort();
+        orphans
+    }
+
+    /// The conversation branching from a post: the post, every post quoting
+    /// it, every post quoting those and so on, in order.
+    pub fn subtree(&self, post_no: u64) -> Vec<u64> {
+        let mut subtree = BTreeSet::new();
+        let mut pending = vec![post_no];
+        while let Some(no) = pending.pop() {
+            if subtree.insert(no) {
+                pending.extend_from_slice(self.backlinks(no));
+            }
+        }
+        subtree.into_iter().collect()
+    }
+
+    /// The number of the post in this thread a quote points to, if it does.
+    fn resolve(&self, target: &QuoteTarget) -> Option<u64> {
+        match *target {
+            QuoteTarget::Post(no) => Some(no),
+            QuoteTarget::Thread { ref board, thread_no, post_no }
+                if thread_no == self.thread_no &&
+                board.as_ref().map_or(true, |b| *b == self.board_name) => {
+                Some(post_no)
+            },
+            _ => None
+        }
+    }
+
+    /// Removes the quotes of a post and the backlinks they gave.
+    fn remove_quotes(&mut self, post_no: u64) {
+        if let Some(quoted) = self.quotes.remove(&post_no) {
+            for no in quoted {
+                if let Some(backlinks) = self.backlinks.get_mut(&no) {
+                    backlinks.retain(|&n| n != post_no);
+                }
+            }
+        }
+        self.external.remove(&post_no);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use QuoteTarget;
+    use testing;
+
+    static QUOTES: &'static str = "{\"posts\": [
+        {\"no\": 100, \"resto\": 0, \"now\": \"\", \"time\": 1492218205},
+        {\"no\": 101, \"resto\": 100, \"now\": \"\", \"time\": 1492218205,
+         \"com\": \"<a href='#p100' class='quotelink'>&gt;&gt;100</a>\"},
+        {\"no\": 102, \"resto\": 100, \"now\": \"\", \"time\": 1492218205,
+         \"com\": \"<a href='#p101' class='quotelink'>&gt;&gt;101</a><br>\
+                   <a href='#p99' class='quotelink'>&gt;&gt;99</a><br>\
+                   <a href='#p101' class='quotelink'>&gt;&gt;101</a>\"},
+        {\"no\": 103, \"resto\": 100, \"now\": \"\", \"time\": 1492218205,
+         \"com\": \"<a href='/g/thread/100#p100' class='quotelink'>\
+                   &gt;&gt;100</a><br>\
+                   <a href='/a/thread/5#p6' class='quotelink'>\
+                   &gt;&gt;&gt;/a/6</a>\"}
+    ]}";
+
+    #[test]
+    fn builds_reply_graph() {
+        let transport = testing::transport();
+        transport.insert_ok(testing::THREAD_URL, QUOTES);
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let thread = g.get_thread(100).unwrap();
+        let graph = thread.reply_graph();
+
+        assert_eq!(&[101, 103], graph.backlinks(100));
+        assert_eq!(&[101, 99], graph.quotes(102));
+        assert_eq!(vec![(102, 99)], graph.orphaned_quotes());
+        assert_eq!(vec![(103, &QuoteTarget::Thread {
+            board: Some("a".to_string()),
+            thread_no: 5,
+            post_no: 6
+        })], graph.external_quotes());
+        assert_eq!(vec![101, 102], graph.subtree(101));
+        assert_eq!(vec![100, 101, 102, 103], graph.subtree(100));
+    }
+
+    #[test]
+    fn updates_incrementally() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let thread = g.get_thread(100).unwrap();
+        assert_eq!(&[101], thread.reply_graph().backlinks(100));
+
+        // 101 is deleted and 102 and 103 are new.
+        transport.insert_ok(testing::THREAD_URL,
+                            &QUOTES.replace("{\"no\": 101", "{\"no\": 104"));
+        let thread = g.get_thread(100).unwrap();
+        let graph = thread.reply_graph();
+        assert_eq!(&[101, 99], graph.quotes(102));
+        assert_eq!(&[102], graph.backlinks(101));
+        assert_eq!(vec![(102, 99), (102, 101)], graph.orphaned_quotes());
+        // Deleted posts keep their quotes.
+        assert_eq!(&[101, 103, 104], graph.backlinks(100));
+    }
+}
@@ -1,30 +1,71 @@
 #![deny(warnings)]
 
 extern crate chrono;
+extern crate futures;
 #[macro_use]
 extern crate hyper;
 #[macro_use]
 extern crate log;
 extern crate regex;
 extern crate reqwest;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
 extern crate time;
 
-pub use self::board::Board;
-pub use self::client::Client;
+pub use self::board::{Board, BoardInfo, BoardList, Catalog, Cooldowns,
+                      IndexPage, Page, ThreadList, ThreadListEntry,
+                      ThreadListPage};
+pub use self::client::{Client, ClientBuilder, API_URL, IMAGE_URL, USER_AGENT,
+                       WEB_URL};
+pub use self::comment::{Comment, Node, QuoteTarget};
+pub use self::disk_cache::{CachingTransport, DISK_CACHE_MAX_BYTES,
+                           DISK_CACHE_MAX_ENTRIES};
 pub use self::error::{Error, Result};
-pub use self::post::{LastReply, Post};
-pub use self::thread::{Thread, ThreadCache, ThreadDeserializer};
+pub use self::graph::ReplyGraph;
+pub use self::post::{ChangeField, LastReply, Post, PostChange};
+pub use self::render::Format;
+pub use self::retry::RetryPolicy;
+pub use self::scheduler::{ApiFuture, Priority, Request, Scheduler, Ticket,
+                          Validators};
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStorage;
+pub use self::storage::{MemoryStorage, Storage, StoredThread};
+pub use self::thread::{EvictionStats, RetentionPolicy, Thread, ThreadCache,
+                       ThreadDelta, ThreadDeserializer, ThreadEvent,
+                       CACHE_FORMAT_VERSION};
+pub use self::transport::{Exchange, HttpTransport, MemoryTransport,
+                          RecordingTransport, ReplayTransport, Response,
+                          Transport};
+pub use self::watcher::{BoardEvent, BoardWatcher, ThreadWatcher, WatchEvent};
 
 mod board;
 mod client;
+mod comment;
+mod disk_cache;
 mod error;
+mod graph;
 mod post;
+mod render;
+mod retry;
+mod scheduler;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod storage;
 mod thread;
+mod transport;
+mod watcher;
+
+#[cfg(test)]
+mod testing;
 
 /// Define a custom If-Modified-Since header because we use `chrono::time`
 /// instead of `time:Tm` and handle date formatting with `chrono`.
 header! { (IfModifiedSince, "If-Modified-Since") => [String] }
+
+/// Define an If-None-Match header that keeps the ETag exactly as the server
+/// sent it.
+header! { (IfNoneMatch, "If-None-Match") => [String] }
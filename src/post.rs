@@ -1,4 +1,5 @@
 use std::fmt;
+use std::mem;
 
 use chrono::{DateTime, NaiveDateTime, UTC};
 
@@ -7,7 +8,7 @@ use chrono::{DateTime, NaiveDateTime, UTC};
 ///
 /// Read more about the Posts object at https://github.com/4chan/4chan-API.
 /// Defaults are for optional fields.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Post {
     pub no: u64,
     pub resto: u64,
@@ -92,7 +93,74 @@ pub struct Post {
     #[serde(default="default::<u16>")]
     pub unique_ips: u16,
     #[serde(default="default::<u16>")]
-    pub tail_size: u16
+    pub tail_size: u16,
+
+    // Extra field set by `Thread::update` to the unix timestamp of when it
+    // noticed the post was deleted. Never sent by the API.
+    #[serde(default="default::<Option<i64>>")]
+    pub deleted_at: Option<i64>
+}
+
+/// Defines `ChangeField` and `Post::changes` from a single list of the
+/// fields that can change after posting, each with its variant.
+macro_rules! change_fields {
+    ($($field:ident => $variant:ident),*) => {
+        /// A field of a `Post` that can change after posting, as compared by
+        /// `Post::changes`. Serializes to the name of the field.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize,
+                 Deserialize)]
+        #[serde(rename_all="snake_case")]
+        pub enum ChangeField {
+            $($variant),*
+        }
+
+        impl ChangeField {
+            /// The name of the field in `Post`, eg. "file_deleted".
+            pub fn name(&self) -> &'static str {
+                match *self {
+                    $(ChangeField::$variant => stringify!($field)),*
+                }
+            }
+        }
+
+        impl Post {
+            /// The changes between this post and a newer version of it,
+            /// stamped with `time`. Only fields that can change after posting
+            /// are compared, not counters such as `replies` or
+            /// `last_modified`.
+            pub fn changes(&self, newer: &Post, time: i64) -> Vec<PostChange> {
+                let mut changes = Vec::new();
+                $(
+                    if self.$field != newer.$field {
+                        changes.push(PostChange {
+                            no: newer.no,
+                            field: ChangeField::$variant,
+                            old: self.$field.to_string(),
+                            new: newer.$field.to_string(),
+                            time: time
+                        });
+                    }
+                )*
+                changes
+            }
+        }
+    }
+}
+
+change_fields! {
+    sticky => Sticky,
+    closed => Closed,
+    archived => Archived,
+    bumplimit => Bumplimit,
+    imagelimit => Imagelimit,
+    name => Name,
+    trip => Trip,
+    capcode => Capcode,
+    sub => Sub,
+    com => Com,
+    filename => Filename,
+    file_deleted => FileDeleted,
+    spoiler => Spoiler
 }
 
 impl Post {
@@ -119,6 +187,39 @@ impl Post {
         Some(::IfModifiedSince(fmt_date))
     }
 
+    /// The comment parsed from its HTML. See `Comment`.
+    pub fn comment(&self) -> ::Comment {
+        ::Comment::parse(&self.com)
+    }
+
+    /// Renders the post, a header with its subject, name, date, number and
+    /// file followed by its comment, as plain text, Markdown or ANSI colored
+    /// text.
+    pub fn render(&self, format: ::Format) -> String {
+        ::render::post(self, format)
+    }
+
+    /// Whether `Thread::update` noticed the post was deleted.
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// The approximate memory use of the post in bytes, counting the text
+    /// it holds.
+    pub fn approx_size(&self) -> usize {
+        let text = [&self.now, &self.name, &self.trip, &self.id,
+                    &self.capcode, &self.country, &self.country_name,
+                    &self.sub, &self.com, &self.filename, &self.ext,
+                    &self.md5, &self.tag, &self.semantic_url];
+        let last_replies = self.last_replies.iter()
+            .map(|r| mem::size_of::<LastReply>() + r.now.len() +
+                 r.name.len() + r.com.len())
+            .sum::<usize>();
+
+        mem::size_of::<Post>() + last_replies +
+            text.iter().map(|s| s.len()).sum::<usize>()
+    }
+
     pub fn is_match(&self, regex: &::regex::Regex) -> bool {
         regex.is_match(&self.name) ||
             regex.is_match(&self.sub) ||
@@ -126,12 +227,15 @@ impl Post {
             regex.is_match(&self.filename)
     }
 
-    pub fn image_url(&self, board_name: &str) -> Option<String> {
+    /// The url of the post's file on the image server at `base_url`, eg.
+    /// `clover::IMAGE_URL`. Returns None if the post has no file.
+    pub fn image_url(&self, base_url: &str, board_name: &str)
+        -> Option<String> {
         if self.filename.is_empty() || self.ext.is_empty() {
             return None
         }
-        Some(format!("https://i.4cdn.org/{}/{}{}",
-                     board_name, self.tim, self.ext))
+        Some(format!("{}/{}/{}{}",
+                     base_url, board_name, self.tim, self.ext))
         }
 }
 
@@ -141,8 +245,20 @@ impl fmt::Display for Post {
     }
 }
 
+/// A `PostChange` is a change to a field of a `Post` noticed by
+/// `Thread::update`, eg. a file deletion or a thread being stickied.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostChange {
+    pub no: u64,
+    pub field: ChangeField,
+    pub old: String,
+    pub new: String,
+    // Unix timestamp of when the change was noticed.
+    pub time: i64
+}
+
 /// A `LastReply` is an abridged form of a `Post` given by a catalog.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct LastReply {
     pub no: u64,
     pub now: String,
@@ -167,7 +283,20 @@ impl Default for LastReply {
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+impl<'a> From<&'a Post> for LastReply {
+    fn from(post: &'a Post) -> LastReply {
+        LastReply {
+            no: post.no,
+            now: post.now.clone(),
+            name: post.name.clone(),
+            com: post.com.clone(),
+            time: post.time as u64,
+            resto: post.resto
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CapcodeReplies {
     #[serde(default="default::<Vec<u64>>")]
     admin: Vec<u64>
@@ -180,7 +309,7 @@ impl Default for CapcodeReplies {
 }
 
 /// Returns the default of a type that implements `Default`.
-fn default<T: Default>() -> T {
+pub fn default<T: Default>() -> T {
     Default::default()
 }
 
@@ -230,10 +359,21 @@ mod test {
             since4pass: 0,
             last_replies: Vec::new(),
             unique_ips: 0,
-            tail_size: 0
+            tail_size: 0,
+            deleted_at: None
         };
 
         let ims = post.if_modified_since().unwrap();
         assert_eq!("Sat, 15 Apr 2017 01:03:25 GMT", &ims.0);
     }
+
+    #[test]
+    fn change_fields_serialize_to_their_names() {
+        let field = ::ChangeField::FileDeleted;
+        assert_eq!("file_deleted", field.name());
+        let json = ::serde_json::to_string(&field).unwrap();
+        assert_eq!("\"file_deleted\"", json);
+        assert_eq!(field, ::serde_json::from_str(&json).unwrap());
+        assert!(::serde_json::from_str::<::ChangeField>("\"tim\"").is_err());
+    }
 }
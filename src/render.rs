@@ -0,0 +1,283 @@
+use std::iter;
+
+use comment::Node;
+
+/// A format `Post::render`, `Thread::render` and `Comment::render` can
+/// render to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    // Text without any markup: entities decoded, line breaks as newlines and
+    // quote links as ">>123".
+    Plain,
+    // CommonMark, with greentext as block quotes, fenced code blocks,
+    // ~~dead links~~ and ||spoilers||.
+    Markdown,
+    // Text colored with ANSI escape codes after 4chan's default style, for
+    // terminals.
+    Ansi
+}
+
+// ANSI SGR parameters.
+static BOLD: &'static str = "1";
+static STRIKE: &'static str = "9";
+static GREEN: &'static str = "32";
+static RED: &'static str = "31";
+static BOLD_RED: &'static str = "1;31";
+static BOLD_GREEN: &'static str = "1;32";
+static BOLD_BLUE: &'static str = "1;34";
+// Black on black, so spoilers can be read by selecting them.
+static HIDDEN: &'static str = "30;40";
+
+struct Renderer {
+    format: Format,
+    out: String,
+    // The ANSI styles in effect, outermost first.
+    styles: Vec<&'static str>,
+    // Whether the current Markdown line is a block quote.
+    in_quote: bool
+}
+
+impl Renderer {
+    fn new(format: Format) -> Renderer {
+        Renderer {
+            format: format,
+            out: String::new(),
+            styles: Vec::new(),
+            in_quote: false
+        }
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            self.node(node);
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        match (self.format, node) {
+            (_, &Node::Text(ref text)) => self.text(text),
+            (Format::Markdown, &Node::LineBreak) => {
+                // A block quote only ends at a blank line.
+                if self.in_quote {
+                    self.out.push_str("\n\n");
+                    self.in_quote = false;
+                } else {
+                    self.out.push_str("  \n");
+                }
+            },
+            (_, &Node::LineBreak) => self.out.push('\n'),
+
+            (Format::Markdown, &Node::Greentext(ref nodes)) => {
+                self.out.push_str("> ");
+                self.in_quote = true;
+                self.nodes(nodes);
+            },
+            (Format::Markdown, &Node::DeadLink { ref text, .. }) => {
+                self.wrap("~~", text);
+            },
+            (Format::Markdown, &Node::Spoiler(ref nodes)) => {
+                self.out.push_str("||");
+                self.nodes(nodes);
+                self.out.push_str("||");
+            },
+            (Format::Markdown, &Node::Code(ref code)) |
+            (Format::Markdown, &Node::Sjis(ref code)) => {
+                if !self.out.is_empty() && !self.out.ends_with('\n') {
+                    self.out.push('\n');
+                }
+                self.out.push_str("```\n");
+                self.out.push_str(code);
+                self.out.push_str("\n```\n");
+            },
+            (Format::Markdown, &Node::Math { ref source, display }) => {
+                let delimiter = if display { "$$" } else { "$" };
+                self.out.push_str(delimiter);
+                self.out.push_str(source);
+                self.out.push_str(delimiter);
+            },
+            (Format::Markdown, &Node::Bold(ref nodes)) |
+            (Format::Markdown, &Node::RedText(ref nodes)) => {
+                self.out.push_str("**");
+                self.nodes(nodes);
+                self.out.push_str("**");
+            },
+            (Format::Markdown, &Node::Banned(ref text)) => {
+                self.wrap("**", text);
+            },
+
+            (_, &Node::Greentext(ref nodes)) => self.styled(GREEN, nodes),
+            (_, &Node::Quote { ref text, .. }) => self.styled_text(RED, text),
+            (_, &Node::DeadLink { ref text, .. }) => {
+                self.styled_text(STRIKE, text)
+            },
+            (_, &Node::Spoiler(ref nodes)) => self.styled(HIDDEN, nodes),
+            (_, &Node::Code(ref code)) | (_, &Node::Sjis(ref code)) => {
+                self.out.push_str(code)
+            },
+            (_, &Node::Math { ref source, display }) => {
+                let tag = if display { "eqn" } else { "math" };
+                self.out.push_str(&format!("[{}]{}[/{}]", tag, source, tag));
+            },
+            (_, &Node::Bold(ref nodes)) => self.styled(BOLD, nodes),
+            (_, &Node::RedText(ref nodes)) => self.styled(BOLD_RED, nodes),
+            (_, &Node::Banned(ref text)) => self.styled_text(BOLD_RED, text)
+        }
+    }
+
+    /// Adds text, escaped for Markdown.
+    fn text(&mut self, text: &str) {
+        if self.format != Format::Markdown {
+            self.out.push_str(text);
+            return
+        }
+
+        for c in text.chars() {
+            if "\\`*_[]<>#~|".contains(c) {
+                self.out.push('\\');
+            }
+            self.out.push(c);
+        }
+    }
+
+    /// Adds text between two Markdown delimiters.
+    fn wrap(&mut self, delimiter: &str, text: &str) {
+        self.out.push_str(delimiter);
+        self.text(text);
+        self.out.push_str(delimiter);
+    }
+
+    /// Adds nodes in an ANSI style. Plain text ignores the style.
+    fn styled(&mut self, style: &'static str, nodes: &[Node]) {
+        self.push_style(style);
+        self.nodes(nodes);
+        self.pop_style();
+    }
+
+    fn styled_text(&mut self, style: &'static str, text: &str) {
+        self.push_style(style);
+        self.text(text);
+        self.pop_style();
+    }
+
+    fn push_style(&mut self, style: &'static str) {
+        if self.format == Format::Ansi {
+            self.out.push_str(&format!("\x1b[{}m", style));
+            self.styles.push(style);
+        }
+    }
+
+    /// Resets the style, then brings back the styles still in effect.
+    fn pop_style(&mut self) {
+        if self.format == Format::Ansi {
+            self.styles.pop();
+            self.out.push_str("\x1b[0m");
+            for style in &self.styles {
+                self.out.push_str(&format!("\x1b[{}m", style));
+            }
+        }
+    }
+
+    /// Adds a field of a post's header, in bold in Markdown.
+    fn header_field(&mut self, style: &'static str, text: &str) {
+        if text.is_empty() {
+            return
+        }
+        if !self.out.is_empty() {
+            self.out.push(' ');
+        }
+        if self.format == Format::Markdown {
+            self.wrap("**", text);
+        } else {
+            self.styled_text(style, text);
+        }
+    }
+}
+
+/// Renders a parsed comment.
+pub fn comment(comment: &::Comment, format: Format) -> String {
+    let mut renderer = Renderer::new(format);
+    renderer.nodes(&comment.nodes);
+    renderer.out
+}
+
+/// Renders a post: a header with its subject, name, date and number, its
+/// file, then its comment.
+pub fn post(post: &::Post, format: Format) -> String {
+    let mut renderer = Renderer::new(format);
+
+    // Subjects and names are HTML escaped too.
+    let subject = ::Comment::parse(&post.sub).render(Format::Plain);
+    let name = ::Comment::parse(&post.name).render(Format::Plain);
+    renderer.header_field(BOLD_BLUE, &subject);
+    renderer.header_field(BOLD_GREEN, &name);
+    renderer.text(&post.trip);
+    if !post.id.is_empty() {
+        renderer.text(&format!(" (ID: {})", post.id));
+    }
+    renderer.text(&format!(" {} No.{}", post.now, post.no));
+    if post.is_deleted() {
+        renderer.text(" [Deleted]");
+    }
+
+    if !post.ext.is_empty() {
+        let filename = ::Comment::parse(&post.filename).render(Format::Plain);
+        renderer.out.push_str(if format == Format::Markdown {
+            "  \n"
+        } else {
+            "\n"
+        });
+        renderer.out.push_str("File: ");
+        renderer.text(&format!("{}{} ({} B, {}x{})", filename, post.ext,
+                               post.fsize, post.w, post.h));
+        if post.file_deleted != 0 {
+            renderer.text(" [Deleted]");
+        }
+    }
+
+    if !post.com.is_empty() {
+        renderer.out.push_str("\n\n");
+        renderer.nodes(&post.comment().nodes);
+    }
+    renderer.out
+}
+
+/// Renders every post of a thread, separated by blank lines.
+pub fn thread(thread: &::Thread, format: Format) -> String {
+    iter::once(&thread.topic).chain(thread.replies.iter())
+        .map(|p| post(p, format))
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::Format;
+
+    static COMMENT: &'static str =
+        "<a href=\"#p101\" class=\"quotelink\">&gt;&gt;101</a><br>\
+         <span class=\"quote\">&gt;implying</span><br>\
+         <s>spoiler</s> <b>*bold*</b><br>\
+         <pre class=\"prettyprint\">x &amp;&amp; y</pre>";
+
+    #[test]
+    fn renders_comments() {
+        let comment = ::Comment::parse(COMMENT);
+
+        assert_eq!(">>101\n>implying\nspoiler *bold*\nx && y",
+                   comment.render(Format::Plain));
+        assert_eq!("\\>\\>101  \n> \\>implying\n\n||spoiler|| **\\*bold\\***  \
+                    \n```\nx && y\n```\n",
+                   comment.render(Format::Markdown));
+        assert_eq!("\x1b[31m>>101\x1b[0m\n\x1b[32m>implying\x1b[0m\n\
+                    \x1b[30;40mspoiler\x1b[0m \x1b[1m*bold*\x1b[0m\nx && y",
+                   comment.render(Format::Ansi));
+    }
+
+    #[test]
+    fn nested_ansi_styles_are_restored() {
+        let comment = ::Comment::parse(
+            "<span class=\"quote\">&gt;a <s>b</s> c</span>");
+        assert_eq!("\x1b[32m>a \x1b[30;40mb\x1b[0m\x1b[32m c\x1b[0m",
+                   comment.render(Format::Ansi));
+    }
+}
@@ -0,0 +1,191 @@
+use std::cmp;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, UTC};
+use reqwest::StatusCode;
+
+/// A `RetryPolicy` decides whether a failed request is made again and how
+/// long the `Scheduler` holds it back before it does.
+///
+/// Server errors (5xx), 429 Too Many Requests and IO errors such as
+/// connection resets are retried (see `Error::is_retryable`). Anything else,
+/// notably 404 Not Found, is returned right away. The delay doubles with
+/// every attempt, starting from `base_delay` up to `max_delay`, unless the
+/// response has a "Retry-After" header, which is honoured up to `max_delay`
+/// so that a bogus value can't hold a request back for hours.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // The number of times a request is made at most, counting the first.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Whether to pick each delay at random between half and all of it, so
+    // that many clients don't all retry at once.
+    pub jitter: bool
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            jitter: true
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates the default `RetryPolicy`: 3 attempts with a delay of 1
+    /// second, then 2 seconds, with jitter.
+    pub fn new() -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// A `RetryPolicy` that never retries.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy { max_attempts: 1, ..RetryPolicy::default() }
+    }
+
+    pub fn max_attempts(mut self, attempts: u32) -> RetryPolicy {
+        self.max_attempts = attempts;
+        self
+    }
+
+    pub fn base_delay(mut self, delay: Duration) -> RetryPolicy {
+        self.base_delay = delay;
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> RetryPolicy {
+        self.max_delay = delay;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: bool) -> RetryPolicy {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Whether a request that has been made `attempts` times and got `res`
+    /// should be made again.
+    pub fn should_retry(&self, attempts: u32, res: &::Result<::Response>)
+        -> bool {
+        if attempts >= self.max_attempts {
+            return false
+        }
+
+        match *res {
+            Ok(ref res) => {
+                res.status == StatusCode::TooManyRequests ||
+                    res.status.is_server_error()
+            },
+            Err(ref e) => e.is_retryable()
+        }
+    }
+
+    /// How long to wait before making a request again after it has been
+    /// made `attempts` times and got `res`.
+    pub fn delay(&self, attempts: u32, res: &::Result<::Response>)
+        -> Duration {
+        if let Ok(ref res) = *res {
+            if let Some(delay) = res.header("Retry-After")
+                .and_then(|value| parse_retry_after(&value)) {
+                return cmp::min(delay, self.max_delay)
+            }
+        }
+
+        let exponent = cmp::min(attempts.saturating_sub(1), 16);
+        let backoff = self.base_delay.checked_mul(1 << exponent)
+            .map_or(self.max_delay, |d| cmp::min(d, self.max_delay));
+
+        if self.jitter {
+            jitter(backoff)
+        } else {
+            backoff
+        }
+    }
+}
+
+/// Parses a "Retry-After" header, which is either a number of seconds or an
+/// HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs))
+    }
+
+    DateTime::parse_from_rfc2822(value).ok().map(|date| {
+        let secs = date.timestamp() - UTC::now().timestamp();
+        Duration::from_secs(cmp::max(secs, 0) as u64)
+    })
+}
+
+/// Picks a duration between half and all of `delay`. Seeded from the clock,
+/// which is plenty to spread out retries.
+fn jitter(delay: Duration) -> Duration {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    // One round of xorshift so consecutive calls don't pick close values.
+    let mut x = nanos ^ 0x9e37_79b9;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+
+    let millis = delay.as_secs() * 1000 +
+        (delay.subsec_nanos() / 1_000_000) as u64;
+    let half = millis / 2;
+    let extra = if half == 0 { 0 } else { x as u64 % (half + 1) };
+    Duration::from_millis(millis - half + extra)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use reqwest::StatusCode;
+
+    fn response(status: StatusCode) -> ::Result<::Response> {
+        Ok(::Response::new(status, ""))
+    }
+
+    #[test]
+    fn retries_only_transient_failures() {
+        let policy = ::RetryPolicy::new();
+        let unavailable = response(StatusCode::ServiceUnavailable);
+        assert!(policy.should_retry(1, &unavailable));
+        assert!(policy.should_retry(1, &response(StatusCode::TooManyRequests)));
+        assert!(!policy.should_retry(1, &response(StatusCode::NotFound)));
+        assert!(!policy.should_retry(1, &response(StatusCode::Ok)));
+        assert!(!policy.should_retry(3, &unavailable));
+        assert!(!::RetryPolicy::none().should_retry(1, &unavailable));
+    }
+
+    #[test]
+    fn backoff_and_retry_after() {
+        let policy = ::RetryPolicy::new()
+            .base_delay(Duration::from_secs(1))
+            .max_delay(Duration::from_secs(5))
+            .jitter(false);
+        let res = response(StatusCode::ServiceUnavailable);
+        assert_eq!(Duration::from_secs(1), policy.delay(1, &res));
+        assert_eq!(Duration::from_secs(2), policy.delay(2, &res));
+        assert_eq!(Duration::from_secs(5), policy.delay(4, &res));
+        assert_eq!(Duration::from_secs(5), policy.delay(100, &res));
+
+        let mut res = ::Response::new(StatusCode::TooManyRequests, "");
+        res.headers.set_raw("Retry-After", vec![b"3".to_vec()]);
+        assert_eq!(Duration::from_secs(3), policy.delay(1, &Ok(res)));
+
+        // Retry-After is capped by the maximum delay.
+        let mut res = ::Response::new(StatusCode::TooManyRequests, "");
+        res.headers.set_raw("Retry-After", vec![b"120".to_vec()]);
+        assert_eq!(Duration::from_secs(5), policy.delay(1, &Ok(res)));
+
+        let jittered = ::RetryPolicy::new().delay(2, &response(
+            StatusCode::ServiceUnavailable));
+        assert!(jittered >= Duration::from_secs(1));
+        assert!(jittered <= Duration::from_secs(2));
+    }
+}
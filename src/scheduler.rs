@@ -0,0 +1,542 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot::{self, Receiver, Sender};
+use reqwest::header::Headers;
+
+/// The minimum interval between any two requests as per the API rules.
+pub const REQUEST_INTERVAL_MS: u64 = 1000;
+
+/// The `Priority` of a `Request`. Among the requests that may be made, the
+/// one with the highest priority goes first, then the one enqueued first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High
+}
+
+/// A `Request` waiting to be made by the `Scheduler`.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub url: String,
+    pub headers: Headers,
+    pub priority: Priority,
+    // The minimum interval between two requests for this resource, eg. 10
+    // seconds for threads.
+    pub min_interval: Option<Duration>,
+    // What `min_interval` applies to, eg. a thread, which is requested
+    // through both its full and its tail JSON. Defaults to the url.
+    pub resource: Option<String>
+}
+
+impl Request {
+    /// Creates a new `Request` of normal priority with no headers.
+    pub fn new(url: &str) -> Request {
+        Request {
+            url: url.to_string(),
+            headers: Headers::new(),
+            priority: Priority::Normal,
+            min_interval: None,
+            resource: None
+        }
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Request {
+        self.priority = priority;
+        self
+    }
+
+    pub fn min_interval(mut self, interval: Duration) -> Request {
+        self.min_interval = Some(interval);
+        self
+    }
+
+    /// Sets what `min_interval` applies to. Requests for the same resource
+    /// share the interval, whatever their url.
+    pub fn resource(mut self, resource: &str) -> Request {
+        self.resource = Some(resource.to_string());
+        self
+    }
+
+    /// Makes the request conditional on `validators`, those of the last 200
+    /// response from the url: they are sent back verbatim as
+    /// "If-Modified-Since" and "If-None-Match", unless those headers are
+    /// already set, so the server can answer with 304 Not Modified. Without
+    /// validators the request stays unconditional.
+    pub fn conditional(mut self, validators: Option<&Validators>) -> Request {
+        if let Some(validators) = validators {
+            validators.apply(&mut self.headers);
+        }
+        self
+    }
+
+    /// The resource `min_interval` applies to.
+    fn resource_id(&self) -> &str {
+        self.resource.as_ref().map(|r| &r[..]).unwrap_or(&self.url[..])
+    }
+}
+
+/// The `Validators` of a response are its "Last-Modified" and "ETag"
+/// headers, exactly as the server sent them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    pub last_modified: Option<String>,
+    pub etag: Option<String>
+}
+
+impl Validators {
+    /// The validators of `res`, or None if it has neither.
+    pub fn from_response(res: &::Response) -> Option<Validators> {
+        let validators = Validators {
+            last_modified: res.header("Last-Modified"),
+            etag: res.header("ETag")
+        };
+        if validators.last_modified.is_none() && validators.etag.is_none() {
+            return None
+        }
+        Some(validators)
+    }
+
+    /// Adds the conditional headers for these validators to `headers`,
+    /// unless they are already set.
+    fn apply(&self, headers: &mut Headers) {
+        if let Some(ref last_modified) = self.last_modified {
+            if !headers.has::<::IfModifiedSince>() {
+                headers.set(::IfModifiedSince(last_modified.clone()));
+            }
+        }
+        if let Some(ref etag) = self.etag {
+            if !headers.has::<::IfNoneMatch>() {
+                headers.set(::IfNoneMatch(etag.clone()));
+            }
+        }
+    }
+}
+
+/// A boxed future resolving to `T`, returned by the async variants of the
+/// blocking API, eg. `Board::catalog_async`.
+pub type ApiFuture<T> = Box<Future<Item=T, Error=::Error> + Send>;
+
+/// A `Ticket` is handed out for every enqueued `Request`. It is a future
+/// resolving to the response once the request has been made, so it can be
+/// driven by an executor or simply blocked on with `wait`.
+#[derive(Debug)]
+pub struct Ticket {
+    receiver: Receiver<::Result<::Response>>
+}
+
+impl Future for Ticket {
+    type Item = ::Response;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<::Response, ::Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(Ok(res))) => Ok(Async::Ready(res)),
+            Ok(Async::Ready(Err(e))) => Err(e),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // The scheduler dropped the request without making it.
+            Err(_) => Err(::Error::SchedulerStopped)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    request: Request,
+    seq: u64,
+    enqueued: Instant,
+    // The number of times the request has been made so far.
+    attempts: u32,
+    // When a request is retried, it isn't made again before this.
+    not_before: Option<Instant>,
+    reply: Sender<::Result<::Response>>
+}
+
+impl Pending {
+    /// Whether this request should be made before `other`.
+    fn before(&self, other: &Pending) -> bool {
+        self.request.priority > other.request.priority ||
+            (self.request.priority == other.request.priority &&
+             self.seq < other.seq)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    queue: Vec<Pending>,
+    next_seq: u64,
+    last_request: Option<Instant>,
+    // Until when each resource may not be requested again, for the
+    // resources whose interval isn't up yet.
+    throttled: HashMap<String, Instant>,
+    served: u32,
+    total_wait: Duration,
+    last_wait: Duration,
+    stopped: bool
+}
+
+impl State {
+    /// Takes the next request that may be made now. Otherwise returns how
+    /// long to wait until one may be made, or None if the queue is empty.
+    fn take_ready(&mut self, now: Instant, interval: Duration)
+        -> Result<Pending, Option<Duration>> {
+        if self.queue.is_empty() {
+            return Err(None)
+        }
+
+        if let Some(last) = self.last_request {
+            if last + interval > now {
+                return Err(Some(last + interval - now))
+            }
+        }
+
+        let mut best: Option<usize> = None;
+        let mut soonest: Option<Instant> = None;
+        for (i, pending) in self.queue.iter().enumerate() {
+            let mut ready_at = match pending.request.min_interval {
+                Some(_) => self.throttled
+                    .get(pending.request.resource_id())
+                    .map_or(now, |&until| until),
+                None => now
+            };
+            if let Some(not_before) = pending.not_before {
+                ready_at = cmp::max(ready_at, not_before);
+            }
+
+            if ready_at > now {
+                soonest = Some(soonest.map_or(ready_at,
+                                              |s| cmp::min(s, ready_at)));
+            } else if best.map_or(true, |b| pending.before(&self.queue[b])) {
+                best = Some(i);
+            }
+        }
+
+        match best {
+            Some(i) => Ok(self.queue.remove(i)),
+            None => Err(soonest.map(|s| s - now))
+        }
+    }
+
+    /// Records that `request` is made at `now`, and forgets the resources
+    /// whose interval is up.
+    fn throttle(&mut self, request: &Request, now: Instant) {
+        let expired = self.throttled.iter()
+            .filter(|&(_, &until)| until <= now)
+            .map(|(resource, _)| resource.clone())
+            .collect::<Vec<String>>();
+        for resource in expired {
+            self.throttled.remove(&resource);
+        }
+
+        if let Some(min) = request.min_interval {
+            self.throttled.insert(request.resource_id().to_string(),
+                                  now + min);
+        }
+    }
+}
+
+/// A `Scheduler` makes every request of a `Client` from a background thread,
+/// one at a time. It enforces the minimum interval between any two requests
+/// (1 second by default) and between two requests for the same resource (as
+/// set on each `Request`), sleeping for exactly the time remaining.
+///
+/// Failed requests are retried as their `RetryPolicy` allows. A retried
+/// request waits in the queue like any other, so it still counts against
+/// the intervals.
+///
+/// Enqueueing never blocks; wait on the returned `Ticket` for the response.
+#[derive(Debug)]
+pub struct Scheduler {
+    shared: Arc<(Mutex<State>, Condvar)>,
+    handle: Option<JoinHandle<()>>
+}
+
+impl Scheduler {
+    /// Creates a new `Scheduler` making requests through `transport` no
+    /// faster than every `interval`, retrying with the default
+    /// `RetryPolicy`.
+    pub fn new(transport: Box<::Transport>, interval: Duration) -> Scheduler {
+        Scheduler::with_retry_policy(transport, interval,
+                                     ::RetryPolicy::default())
+    }
+
+    /// Creates a new `Scheduler` retrying failed requests with `retry`.
+    pub fn with_retry_policy(transport: Box<::Transport>, interval: Duration,
+                             retry: ::RetryPolicy) -> Scheduler {
+        let shared = Arc::new((Mutex::new(State {
+            queue: Vec::new(),
+            next_seq: 0,
+            last_request: None,
+            throttled: HashMap::new(),
+            served: 0,
+            total_wait: Duration::from_secs(0),
+            last_wait: Duration::from_secs(0),
+            stopped: false
+        }), Condvar::new()));
+
+        let worker_shared = shared.clone();
+        let handle = thread::spawn(move || {
+            run(worker_shared, transport, interval, retry);
+        });
+
+        Scheduler { shared: shared, handle: Some(handle) }
+    }
+
+    /// Adds a request to the queue.
+    pub fn enqueue(&self, request: Request) -> Ticket {
+        let (tx, rx) = oneshot::channel();
+        let &(ref lock, ref cvar) = &*self.shared;
+
+        let mut state = lock.lock().unwrap();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.queue.push(Pending {
+            request: request,
+            seq: seq,
+            enqueued: Instant::now(),
+            attempts: 0,
+            not_before: None,
+            reply: tx
+        });
+        cvar.notify_one();
+
+        Ticket { receiver: rx }
+    }
+
+    /// The number of requests waiting to be made.
+    pub fn queue_depth(&self) -> usize {
+        self.shared.0.lock().unwrap().queue.len()
+    }
+
+    /// The average time requests waited in the queue before being made.
+    /// Each retry counts as a request made again, which waited from the end
+    /// of its backoff delay.
+    pub fn average_wait(&self) -> Duration {
+        let state = self.shared.0.lock().unwrap();
+        if state.served == 0 {
+            return Duration::from_secs(0)
+        }
+        state.total_wait / state.served
+    }
+
+    /// The time the last request made waited in the queue.
+    pub fn last_wait(&self) -> Duration {
+        self.shared.0.lock().unwrap().last_wait
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        {
+            let &(ref lock, ref cvar) = &*self.shared;
+            lock.lock().unwrap().stopped = true;
+            cvar.notify_one();
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run(shared: Arc<(Mutex<State>, Condvar)>, transport: Box<::Transport>,
+       interval: Duration, retry: ::RetryPolicy) {
+    let &(ref lock, ref cvar) = &*shared;
+
+    loop {
+        let mut next = None;
+        {
+            let mut state = lock.lock().unwrap();
+            while next.is_none() {
+                if state.stopped { return }
+
+                let now = Instant::now();
+                let ready = state.take_ready(now, interval);
+                match ready {
+                    Ok(pending) => {
+                        // A retry waits from the end of its backoff, not
+                        // from when the request was first enqueued.
+                        let since = pending.not_before
+                            .unwrap_or(pending.enqueued);
+                        let wait = now - since;
+                        state.last_request = Some(now);
+                        state.throttle(&pending.request, now);
+                        state.served += 1;
+                        state.total_wait += wait;
+                        state.last_wait = wait;
+                        next = Some(pending);
+                    },
+                    Err(Some(wait)) => {
+                        state = cvar.wait_timeout(state, wait).unwrap().0;
+                    },
+                    Err(None) => {
+                        state = cvar.wait(state).unwrap();
+                    }
+                }
+            }
+        }
+
+        let mut pending = next.unwrap();
+        debug!("Making request to url: {} with headers: {:?}",
+               pending.request.url, pending.request.headers);
+        let res = transport.get(&pending.request.url,
+                                pending.request.headers.clone());
+        pending.attempts += 1;
+
+        if retry.should_retry(pending.attempts, &res) {
+            let delay = retry.delay(pending.attempts, &res);
+            warn!("Request to {} failed (attempt {}), retrying in {:?}",
+                  pending.request.url, pending.attempts, delay);
+            pending.not_before = Some(Instant::now() + delay);
+            lock.lock().unwrap().queue.push(pending);
+            continue
+        }
+
+        // The requester may have given up on the ticket.
+        let _ = pending.reply.send(res);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use futures::Future;
+
+    #[test]
+    fn scheduler_enforces_intervals() {
+        let transport = ::MemoryTransport::new();
+        transport.insert_ok("http://test/a.json", "a");
+        let scheduler = ::Scheduler::new(Box::new(transport.clone()),
+                                         Duration::from_millis(20));
+
+        let start = Instant::now();
+        let tickets = (0..3)
+            .map(|_| scheduler.enqueue(::Request::new("http://test/a.json")))
+            .collect::<Vec<::Ticket>>();
+        for ticket in tickets {
+            assert_eq!("a", ticket.wait().unwrap().body);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        let start = Instant::now();
+        let first = scheduler.enqueue(::Request::new("http://test/a.json")
+            .min_interval(Duration::from_millis(100)));
+        let second = scheduler.enqueue(::Request::new("http://test/a.json")
+            .min_interval(Duration::from_millis(100)));
+        first.wait().unwrap();
+        second.wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(0, scheduler.queue_depth());
+
+        // Urls of the same resource share its interval.
+        transport.insert_ok("http://test/a-tail.json", "a");
+        let start = Instant::now();
+        let first = scheduler.enqueue(::Request::new("http://test/a.json")
+            .min_interval(Duration::from_millis(100))
+            .resource("a"));
+        let second = scheduler.enqueue(::Request::new("http://test/a-tail.json")
+            .min_interval(Duration::from_millis(100))
+            .resource("a"));
+        first.wait().unwrap();
+        second.wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn scheduler_retries_failed_requests() {
+        use reqwest::StatusCode;
+
+        let transport = ::MemoryTransport::new();
+        transport.insert("http://test/busy",
+                         ::Response::new(StatusCode::ServiceUnavailable, ""));
+        let retry = ::RetryPolicy::new()
+            .base_delay(Duration::from_millis(50))
+            .jitter(false);
+        let scheduler = ::Scheduler::with_retry_policy(
+            Box::new(transport.clone()), Duration::from_millis(0), retry);
+
+        let res = scheduler.enqueue(::Request::new("http://test/busy"))
+            .wait()
+            .unwrap();
+        assert_eq!(StatusCode::ServiceUnavailable, res.status);
+        assert_eq!(3, transport.requests().len());
+        // The backoff delays don't count as waiting in the queue.
+        assert!(scheduler.average_wait() < Duration::from_millis(25));
+
+        // Not found is final.
+        scheduler.enqueue(::Request::new("http://test/gone")).wait().unwrap();
+        assert_eq!(4, transport.requests().len());
+    }
+
+    #[test]
+    fn conditional_requests_send_validators() {
+        let transport = ::MemoryTransport::new();
+        transport.insert_ok("http://test/a.json", "a");
+        let scheduler = ::Scheduler::new(Box::new(transport.clone()),
+                                         Duration::from_millis(0));
+        let validators = ::Validators {
+            last_modified: Some("Sat, 15 Apr 2017 01:03:25 GMT".to_string()),
+            etag: Some("\"58f170dd-1\"".to_string())
+        };
+
+        scheduler.enqueue(::Request::new("http://test/a.json")
+                          .conditional(Some(&validators)))
+            .wait()
+            .unwrap();
+        scheduler.enqueue(::Request::new("http://test/a.json")
+                          .conditional(None))
+            .wait()
+            .unwrap();
+        // Headers that are already set are kept.
+        let mut request = ::Request::new("http://test/a.json");
+        request.headers.set(::IfNoneMatch("\"2\"".to_string()));
+        scheduler.enqueue(request.conditional(Some(&validators)))
+            .wait()
+            .unwrap();
+
+        let requests = transport.requests();
+        assert_eq!(Some(&::IfModifiedSince(
+            "Sat, 15 Apr 2017 01:03:25 GMT".to_string())),
+                   requests[0].1.get::<::IfModifiedSince>());
+        assert_eq!(Some(&::IfNoneMatch("\"58f170dd-1\"".to_string())),
+                   requests[0].1.get::<::IfNoneMatch>());
+        assert!(!requests[1].1.has::<::IfModifiedSince>());
+        assert!(!requests[1].1.has::<::IfNoneMatch>());
+        assert!(requests[2].1.has::<::IfModifiedSince>());
+        assert_eq!(Some(&::IfNoneMatch("\"2\"".to_string())),
+                   requests[2].1.get::<::IfNoneMatch>());
+    }
+
+    #[test]
+    fn scheduler_orders_by_priority() {
+        let transport = ::MemoryTransport::new();
+        let scheduler = ::Scheduler::new(Box::new(transport.clone()),
+                                         Duration::from_millis(50));
+
+        // Both requests are queued while the interval after the first one
+        // isn't up yet.
+        scheduler.enqueue(::Request::new("http://test/first"))
+            .wait()
+            .unwrap();
+        let tickets = vec![
+            scheduler.enqueue(::Request::new("http://test/low")
+                              .priority(::Priority::Low)),
+            scheduler.enqueue(::Request::new("http://test/high")
+                              .priority(::Priority::High))
+        ];
+        for ticket in tickets {
+            ticket.wait().unwrap();
+        }
+
+        let urls = transport.requests().into_iter()
+            .map(|(url, _)| url)
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["http://test/first", "http://test/high",
+                        "http://test/low"], urls);
+    }
+}
@@ -0,0 +1,260 @@
+use std::iter;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{self, Connection};
+
+use Storage;
+
+static SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS boards (
+        name TEXT PRIMARY KEY
+    );
+
+    CREATE TABLE IF NOT EXISTS threads (
+        board TEXT NOT NULL REFERENCES boards (name),
+        no INTEGER NOT NULL,
+        subject TEXT NOT NULL,
+        time INTEGER NOT NULL,
+        last_modified INTEGER NOT NULL,
+        expired INTEGER NOT NULL,
+        expired_at INTEGER,
+        PRIMARY KEY (board, no)
+    );
+    CREATE INDEX IF NOT EXISTS threads_by_time ON threads (board, time);
+
+    CREATE TABLE IF NOT EXISTS posts (
+        board TEXT NOT NULL,
+        no INTEGER NOT NULL,
+        thread_no INTEGER NOT NULL,
+        time INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        trip TEXT NOT NULL,
+        poster_id TEXT NOT NULL,
+        capcode TEXT NOT NULL,
+        country TEXT NOT NULL,
+        subject TEXT NOT NULL,
+        comment TEXT NOT NULL,
+        deleted_at INTEGER,
+        -- The whole post as json, to restore it without losing any field.
+        data TEXT NOT NULL,
+        PRIMARY KEY (board, no),
+        FOREIGN KEY (board, thread_no) REFERENCES threads (board, no)
+    );
+    CREATE INDEX IF NOT EXISTS posts_by_thread
+        ON posts (board, thread_no, poster_id);
+
+    CREATE TABLE IF NOT EXISTS files (
+        board TEXT NOT NULL,
+        post_no INTEGER NOT NULL,
+        tim INTEGER NOT NULL,
+        filename TEXT NOT NULL,
+        ext TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        md5 TEXT NOT NULL,
+        width INTEGER NOT NULL,
+        height INTEGER NOT NULL,
+        deleted INTEGER NOT NULL,
+        PRIMARY KEY (board, post_no),
+        FOREIGN KEY (board, post_no) REFERENCES posts (board, no)
+    );
+    CREATE INDEX IF NOT EXISTS files_by_md5 ON files (md5);
+";
+
+/// A `Storage` keeping threads in an embedded SQLite database, with a table
+/// each for boards, threads, posts and files. Enable the "sqlite" feature
+/// to use it.
+///
+/// Storing a thread replaces its rows, so the database always holds the
+/// latest version of every post, with deleted posts marked by `deleted_at`.
+/// SQLite only ignores the case of ASCII letters when matching subjects.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    conn: Mutex<Connection>
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it and its tables if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> ::Result<SqliteStorage> {
+        SqliteStorage::from_connection(try!(Connection::open(path)))
+    }
+
+    /// Opens a database that only lives in memory.
+    pub fn open_in_memory() -> ::Result<SqliteStorage> {
+        SqliteStorage::from_connection(try!(Connection::open_in_memory()))
+    }
+
+    fn from_connection(conn: Connection) -> ::Result<SqliteStorage> {
+        try!(conn.execute_batch(SCHEMA));
+        Ok(SqliteStorage { conn: Mutex::new(conn) })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn store_thread(&self, thread: &::Thread) -> ::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = try!(conn.transaction());
+        let board: &str = &thread.board_name;
+        let topic = &thread.topic;
+
+        try!(tx.execute("INSERT OR IGNORE INTO boards (name) VALUES (?1)",
+                        &[&board]));
+        try!(tx.execute(
+            "INSERT OR REPLACE INTO threads
+             (board, no, subject, time, last_modified, expired, expired_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            &[&board, &(topic.no as i64), &topic.sub, &(topic.time as i64),
+              &topic.last_modified, &(thread.expired as i64),
+              &thread.expired_at]));
+
+        for post in iter::once(topic).chain(thread.replies.iter()) {
+            try!(store_post(&tx, board, topic.no, post));
+        }
+
+        try!(tx.commit());
+        Ok(())
+    }
+
+    fn load_thread(&self, board_name: &str, thread_no: u64)
+        -> ::Result<Option<::StoredThread>> {
+        let conn = self.conn.lock().unwrap();
+        let thread = conn.query_row(
+            "SELECT expired, expired_at FROM threads
+             WHERE board = ?1 AND no = ?2",
+            &[&board_name, &(thread_no as i64)],
+            |row| {
+                let expired: i64 = row.get(0);
+                let expired_at: Option<i64> = row.get(1);
+                (expired != 0, expired_at)
+            });
+        let (expired, expired_at) = match thread {
+            Ok(thread) => thread,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(::Error::from(e))
+        };
+
+        let mut stmt = try!(conn.prepare(
+            "SELECT data FROM posts WHERE board = ?1 AND thread_no = ?2
+             ORDER BY no"));
+        let rows = try!(stmt.query_map(&[&board_name, &(thread_no as i64)],
+                                       |row| -> String { row.get(0) }));
+        let mut posts = try!(read_posts(rows)).into_iter();
+        let topic = match posts.next() {
+            Some(topic) => topic,
+            None => return Err(::Error::EmptyThread)
+        };
+
+        Ok(Some(::StoredThread {
+            board_name: board_name.to_string(),
+            topic: topic,
+            replies: posts.collect(),
+            expired: expired,
+            expired_at: expired_at
+        }))
+    }
+
+    fn posts_by_id(&self, board_name: &str, thread_no: u64, id: &str)
+        -> ::Result<Vec<::Post>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = try!(conn.prepare(
+            "SELECT data FROM posts
+             WHERE board = ?1 AND thread_no = ?2 AND poster_id = ?3
+             ORDER BY no"));
+        let rows = try!(stmt.query_map(
+            &[&board_name, &(thread_no as i64), &id],
+            |row| -> String { row.get(0) }));
+        read_posts(rows)
+    }
+
+    fn threads_by_subject(&self, board_name: &str, query: &str, since: i64)
+        -> ::Result<Vec<::Post>> {
+        let pattern = format!("%{}%", query.replace('\\', "\\\\")
+                              .replace('%', "\\%")
+                              .replace('_', "\\_"));
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = try!(conn.prepare(
+            "SELECT posts.data FROM threads
+             JOIN posts ON posts.board = threads.board
+                 AND posts.no = threads.no
+             WHERE threads.board = ?1
+                 AND threads.subject LIKE ?2 ESCAPE '\\'
+                 AND threads.time >= ?3
+             ORDER BY threads.no"));
+        let rows = try!(stmt.query_map(&[&board_name, &pattern, &since],
+                                       |row| -> String { row.get(0) }));
+        read_posts(rows)
+    }
+}
+
+/// Inserts or replaces a post of the thread `thread_no`, and its file.
+fn store_post(conn: &Connection, board: &str, thread_no: u64,
+              post: &::Post) -> ::Result<()> {
+    let data = try!(::serde_json::to_string(post));
+    try!(conn.execute(
+        "INSERT OR REPLACE INTO posts
+         (board, no, thread_no, time, name, trip, poster_id, capcode,
+          country, subject, comment, deleted_at, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        &[&board, &(post.no as i64), &(thread_no as i64), &(post.time as i64),
+          &post.name, &post.trip, &post.id, &post.capcode, &post.country,
+          &post.sub, &post.com, &post.deleted_at, &data]));
+
+    if !post.ext.is_empty() {
+        try!(conn.execute(
+            "INSERT OR REPLACE INTO files
+             (board, post_no, tim, filename, ext, size, md5, width, height,
+              deleted)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            &[&board, &(post.no as i64), &(post.tim as i64), &post.filename,
+              &post.ext, &(post.fsize as i64), &post.md5, &(post.w as i64),
+              &(post.h as i64), &(post.file_deleted as i64)]));
+    }
+    Ok(())
+}
+
+/// Deserializes the json of each post in `rows`.
+fn read_posts<I>(rows: I) -> ::Result<Vec<::Post>>
+    where I: Iterator<Item=rusqlite::Result<String>> {
+    let mut posts = Vec::new();
+    for data in rows {
+        posts.push(try!(::serde_json::from_str(&try!(data))));
+    }
+    Ok(posts)
+}
+
+#[cfg(test)]
+mod test {
+    use Storage;
+    use testing;
+
+    #[test]
+    fn stores_and_queries_threads() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let mut thread = g.get_thread(100).unwrap();
+        thread.replies[0].id = "Abc123".to_string();
+        thread.replies[0].deleted_at = Some(1492218400);
+
+        let storage = ::SqliteStorage::open_in_memory().unwrap();
+        storage.store_thread(&thread).unwrap();
+        // Storing again replaces the thread rather than duplicating it.
+        storage.store_thread(&thread).unwrap();
+
+        let stored = storage.load_thread("g", 100).unwrap().unwrap();
+        assert_eq!(100, stored.topic.no);
+        assert_eq!(1, stored.replies.len());
+        assert!(stored.replies[0].is_deleted());
+        assert!(storage.load_thread("g", 200).unwrap().is_none());
+
+        let posts = storage.posts_by_id("g", 100, "Abc123").unwrap();
+        assert_eq!(vec![101], posts.iter().map(|p| p.no).collect::<Vec<_>>());
+
+        let topics = storage.threads_by_subject("g", "GENTOO", 1492218205)
+            .unwrap();
+        assert_eq!(1, topics.len());
+        assert!(storage.threads_by_subject("g", "gen_oo", 0).unwrap()
+                .is_empty());
+        assert!(storage.threads_by_subject("b", "gentoo", 0).unwrap()
+                .is_empty());
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A `Storage` keeps threads beyond the lifetime of a `ThreadCache`, eg. to
+/// archive boards.
+///
+/// Back a `ThreadCache` with one with `ThreadCache::set_storage`. Its
+/// threads, and clones of them, are then upserted after every update (see
+/// `Thread::set_storage`). A `Board` also stores threads when it fetches
+/// them and when they are evicted, and `Board::get_thread` restores threads
+/// that are stored but not cached instead of fetching them again.
+///
+/// `MemoryStorage` keeps threads in memory, and `SqliteStorage` (behind the
+/// "sqlite" feature) in an embedded SQLite database.
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// Stores a thread and all its posts, replacing what was stored of it.
+    fn store_thread(&self, thread: &::Thread) -> ::Result<()>;
+
+    /// The stored thread numbered `thread_no` on a board, if any.
+    fn load_thread(&self, board_name: &str, thread_no: u64)
+        -> ::Result<Option<StoredThread>>;
+
+    /// The posts in a thread by the poster with the ID `id`, in order.
+    fn posts_by_id(&self, board_name: &str, thread_no: u64, id: &str)
+        -> ::Result<Vec<::Post>>;
+
+    /// The topics of the threads on a board whose subject contains `query`,
+    /// ignoring case, that were posted at or after the unix timestamp
+    /// `since`, in order. An empty query matches every thread.
+    fn threads_by_subject(&self, board_name: &str, query: &str, since: i64)
+        -> ::Result<Vec<::Post>>;
+}
+
+/// A thread as kept by a `Storage`. Turn it back into a `Thread` with
+/// `Thread::from_stored`.
+#[derive(Clone, Debug)]
+pub struct StoredThread {
+    pub board_name: String,
+    pub topic: ::Post,
+    pub replies: Vec<::Post>,
+    pub expired: bool,
+    pub expired_at: Option<i64>
+}
+
+impl<'a> From<&'a ::Thread> for StoredThread {
+    fn from(thread: &'a ::Thread) -> StoredThread {
+        StoredThread {
+            board_name: thread.board_name.clone(),
+            topic: thread.topic.clone(),
+            replies: thread.replies.clone(),
+            expired: thread.expired,
+            expired_at: thread.expired_at
+        }
+    }
+}
+
+/// A `Storage` keeping threads in memory. Clones share the same threads,
+/// so keep one to look at what was stored, eg. in tests.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStorage {
+    threads: Arc<Mutex<HashMap<(String, u64), StoredThread>>>
+}
+
+impl MemoryStorage {
+    /// Creates a new, empty `MemoryStorage`.
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    /// The number of stored threads.
+    pub fn len(&self) -> usize {
+        self.threads.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn store_thread(&self, thread: &::Thread) -> ::Result<()> {
+        let key = (thread.board_name.clone(), thread.topic.no);
+        self.threads.lock().unwrap().insert(key, StoredThread::from(thread));
+        Ok(())
+    }
+
+    fn load_thread(&self, board_name: &str, thread_no: u64)
+        -> ::Result<Option<StoredThread>> {
+        let key = (board_name.to_string(), thread_no);
+        Ok(self.threads.lock().unwrap().get(&key).cloned())
+    }
+
+    fn posts_by_id(&self, board_name: &str, thread_no: u64, id: &str)
+        -> ::Result<Vec<::Post>> {
+        let key = (board_name.to_string(), thread_no);
+        Ok(match self.threads.lock().unwrap().get(&key) {
+            Some(thread) => {
+                ::std::iter::once(&thread.topic)
+                    .chain(thread.replies.iter())
+                    .filter(|p| p.id == id)
+                    .cloned()
+                    .collect()
+            },
+            None => Vec::new()
+        })
+    }
+
+    fn threads_by_subject(&self, board_name: &str, query: &str, since: i64)
+        -> ::Result<Vec<::Post>> {
+        let query = query.to_lowercase();
+        let mut topics = self.threads.lock().unwrap().values()
+            .filter(|t| t.board_name == board_name)
+            .filter(|t| t.topic.time as i64 >= since)
+            .filter(|t| t.topic.sub.to_lowercase().contains(&query))
+            .map(|t| t.topic.clone())
+            .collect::<Vec<::Post>>();
+        topics.sort_by_key(|t| t.no);
+        Ok(topics)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use reqwest::StatusCode;
+
+    use Storage;
+    use testing;
+
+    fn board(transport: &::MemoryTransport, storage: &::MemoryStorage)
+        -> ::Board {
+        let g = ::Board::new(testing::client(transport), "g").unwrap();
+        g.thread_cache.lock().unwrap()
+            .set_storage(Arc::new(storage.clone()));
+        g
+    }
+
+    #[test]
+    fn stores_and_restores_threads() {
+        let transport = testing::transport();
+        let storage = ::MemoryStorage::new();
+        let g = board(&transport, &storage);
+        // Topics from the catalog aren't worth storing yet.
+        let _ = g.catalog().unwrap();
+        assert!(storage.is_empty());
+
+        // Updates are stored, including the thread expiring.
+        g.get_thread(100).unwrap();
+        g.get_thread(200).unwrap();
+        let stored = storage.load_thread("g", 100).unwrap().unwrap();
+        assert_eq!(1, stored.replies.len());
+        assert!(storage.load_thread("g", 200).unwrap().unwrap().expired);
+
+        assert_eq!(1, storage.threads_by_subject("g", "GENTOO", 0)
+                   .unwrap().len());
+        assert!(storage.threads_by_subject("g", "gentoo", 1492218206)
+                .unwrap().is_empty());
+
+        // A board with an empty cache picks up where the other left off.
+        transport.insert(testing::THREAD_URL,
+                         ::Response::new(StatusCode::NotModified, ""));
+        let g = board(&transport, &storage);
+        let thread = g.get_thread(100).unwrap();
+        assert_eq!(1, thread.replies.len());
+
+        // Only explicit lookups restore threads, and expired ones aren't
+        // requested again.
+        let g = board(&transport, &storage);
+        assert!(g.find_cached("gentoo").unwrap().is_empty());
+        let requests = transport.requests().len();
+        assert!(g.get_thread(200).unwrap().is_expired());
+        assert_eq!(requests, transport.requests().len());
+    }
+
+    #[test]
+    fn updates_of_cloned_threads_are_stored() {
+        let transport = testing::transport();
+        let storage = ::MemoryStorage::new();
+        let g = board(&transport, &storage);
+        let _ = g.catalog().unwrap();
+
+        let mut thread = g.thread_cache.lock().unwrap().get(100).cloned()
+            .unwrap();
+        thread.update().unwrap();
+        let stored = storage.load_thread("g", 100).unwrap().unwrap();
+        assert_eq!(1, stored.replies.len());
+    }
+
+    #[test]
+    fn stores_evicted_threads() {
+        let transport = testing::transport();
+        let storage = ::MemoryStorage::new();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        g.get_thread(100).unwrap();
+
+        let mut cache = g.thread_cache.lock().unwrap();
+        cache.set_storage(Arc::new(storage.clone()));
+        cache.set_policy(::RetentionPolicy::default().max_threads(1));
+        drop(cache);
+
+        // Thread 200 from the catalog pushes out 100, which gets stored.
+        let _ = g.catalog().unwrap();
+        assert!(!g.thread_cache.lock().unwrap().contains(100));
+        assert!(storage.load_thread("g", 100).unwrap().is_some());
+        assert_eq!(1, storage.len());
+    }
+}
@@ -0,0 +1,85 @@
+//! Canned API responses shared by the offline tests.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub static BOARDS_URL: &'static str = "https://a.4cdn.org/boards.json";
+pub static CATALOG_URL: &'static str = "https://a.4cdn.org/g/catalog.json";
+pub static THREAD_URL: &'static str = "https://a.4cdn.org/g/thread/100.json";
+
+pub static BOARDS: &'static str = r#"{"boards": [
+    {"board": "g", "title": "Technology", "ws_board": 1},
+    {"board": "b", "title": "Random", "ws_board": 0}
+]}"#;
+
+pub static CATALOG: &'static str = r#"[
+    {"page": 1, "threads": [
+        {"no": 100, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+         "time": 1492218205, "sub": "installgentoo", "com": "Ricing general",
+         "replies": 1, "last_modified": 1492218205},
+        {"no": 200, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+         "time": 1492218205, "sub": "Desktop thread", "replies": 0,
+         "last_modified": 1492218205}
+    ]}
+]"#;
+
+pub static THREAD: &'static str = r#"{"posts": [
+    {"no": 100, "resto": 0, "now": "04/15/17(Sat)01:03:25", "time": 1492218205,
+     "sub": "installgentoo", "com": "Ricing general", "replies": 1,
+     "last_modified": 1492218300},
+    {"no": 101, "resto": 100, "now": "04/15/17(Sat)01:05:00",
+     "time": 1492218300,
+     "com": "<a href=\"#p100\" class=\"quotelink\">&gt;&gt;100</a><br>No."}
+]}"#;
+
+/// A `MemoryTransport` serving boards.json, the /g/ catalog and thread 100.
+pub fn transport() -> ::MemoryTransport {
+    let transport = ::MemoryTransport::new();
+    transport.insert_ok(BOARDS_URL, BOARDS);
+    transport.insert_ok(CATALOG_URL, CATALOG);
+    transport.insert_ok(THREAD_URL, THREAD);
+    transport
+}
+
+/// A `Client` making its requests through `transport`, without any
+/// throttling.
+pub fn client(transport: &::MemoryTransport) -> ::Client {
+    ::ClientBuilder::new()
+        .transport(Box::new(transport.clone()))
+        .request_interval(Duration::from_millis(0))
+        .thread_interval(Duration::from_millis(0))
+        .build()
+        .unwrap()
+}
+
+/// A fresh directory under the system's temporary directory, named after
+/// the test and the time it was created so that concurrent test runs don't
+/// share it. Removed with everything in it when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64)
+            .unwrap_or(0);
+        let path = env::temp_dir().join(format!("clover-{}-{}", name, nanos));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
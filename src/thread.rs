@@ -1,37 +1,123 @@
-use std::collections::HashMap;
-use std::io::Read;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::sync::{Arc, Mutex};
-use std::thread::sleep;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::iter;
+use std::mem;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
-use chrono::{DateTime, Duration, UTC};
+use chrono::{DateTime, NaiveDateTime, UTC};
+use futures::{future, Future};
+use futures::future::Either;
 use reqwest::StatusCode;
+use serde::Serializer;
+
+/// The minimum interval between two updates of a thread as per the API
+/// rules.
+pub const THREAD_INTERVAL_SECS: u64 = 10;
+
+/// The version of the format written by `ThreadCache::save`.
+pub const CACHE_FORMAT_VERSION: u32 = 1;
 
 /// A `Thread` is a 4chan thread. Its topic is the OP `Post` and its replies
 /// are every reply in the thread.
-#[derive(Clone, Debug)]
+///
+/// Serializes to the format saved by `ThreadCache::save`, without the client.
+#[derive(Clone, Debug, Serialize)]
 pub struct Thread {
     pub board_name: String,
-    client: Arc<Mutex<::Client>>,
+    #[serde(skip_serializing)]
+    client: ::Client,
     pub topic: ::Post,
     pub replies: Vec<::Post>,
+    // Every change to the topic or a reply noticed by `update`, oldest
+    // first. Deleted replies are kept in `replies` with `deleted_at` set.
+    pub changes: Vec<::PostChange>,
     pub expired: bool,
+    // Unix timestamp of when the thread was noticed to have expired or been
+    // archived.
+    pub expired_at: Option<i64>,
+    wants_update: bool,
+    last_reply_no: u64,
+    #[serde(serialize_with="serialize_timestamp")]
+    last_updated: Option<DateTime<UTC>>,
+    // The validators of the last 200 response to the thread's JSON and to
+    // its tail JSON.
+    validators: Option<::Validators>,
+    tail_validators: Option<::Validators>,
+    // Rebuilt from the posts when the thread is restored.
+    #[serde(skip_serializing)]
+    graph: ::ReplyGraph,
+    // Where every update is upserted, set by the `ThreadCache` the thread
+    // was added to.
+    #[serde(skip_serializing)]
+    storage: Option<Arc<::Storage>>
+}
+
+/// Serializes a date as a unix timestamp.
+fn serialize_timestamp<S>(date: &Option<DateTime<UTC>>, serializer: S)
+    -> Result<S::Ok, S::Error> where S: Serializer {
+    match *date {
+        Some(date) => serializer.serialize_some(&date.timestamp()),
+        None => serializer.serialize_none()
+    }
+}
+
+/// A `Thread` as read back from a saved `ThreadCache`.
+#[derive(Deserialize)]
+struct SavedThread {
+    board_name: String,
+    topic: ::Post,
+    replies: Vec<::Post>,
+    changes: Vec<::PostChange>,
+    expired: bool,
+    expired_at: Option<i64>,
     wants_update: bool,
     last_reply_no: u64,
-    last_updated: Option<DateTime<UTC>>
+    last_updated: Option<i64>,
+    validators: Option<::Validators>,
+    tail_validators: Option<::Validators>
+}
+
+#[derive(Serialize)]
+struct CacheFile<'a> {
+    version: u32,
+    threads: Vec<&'a Thread>,
+    pinned: Vec<u64>,
+    // The validators of other urls, eg. the catalog of the threads' board.
+    validators: Vec<(String, ::Validators)>
+}
+
+#[derive(Deserialize)]
+struct SavedCache {
+    version: u32,
+    threads: Vec<SavedThread>,
+    pinned: Vec<u64>,
+    validators: Vec<(String, ::Validators)>
+}
+
+/// Read before the rest of a saved cache, to check it can be read at all.
+#[derive(Deserialize)]
+struct SavedVersion {
+    version: u32
 }
 
 impl Thread {
     /// Creates a new `Thread` from a topic `Post`.
     pub fn from_topic(post: ::Post,
                       board_name: &str,
-                      client: Arc<Mutex<::Client>>) -> Thread {
+                      client: ::Client) -> Thread {
         Thread {
             board_name: board_name.to_string(),
             client: client,
             topic: post.clone(),
             replies: Vec::new(),
+            changes: Vec::new(),
             expired: false,
+            expired_at: None,
             wants_update: true,
             last_reply_no: {
                 if !post.last_replies.is_empty() {
@@ -40,107 +126,491 @@ impl Thread {
                     0
                 }
             },
-            last_updated: None
-        }
+            last_updated: None,
+            validators: None,
+            tail_validators: None,
+            graph: ::ReplyGraph::default(),
+            storage: None
+        }.with_graph()
     }
 
-    /// Creates a new `Thread` from a `ThreadDeserializer`.
+    /// Restores a `Thread` saved with `ThreadCache::save`.
+    fn from_saved(saved: SavedThread, client: ::Client) -> Thread {
+        Thread {
+            board_name: saved.board_name,
+            client: client,
+            topic: saved.topic,
+            replies: saved.replies,
+            changes: saved.changes,
+            expired: saved.expired,
+            expired_at: saved.expired_at,
+            wants_update: saved.wants_update,
+            last_reply_no: saved.last_reply_no,
+            last_updated: saved.last_updated.map(|timestamp| {
+                DateTime::<UTC>::from_utc(
+                    NaiveDateTime::from_timestamp(timestamp, 0), UTC)
+            }),
+            validators: saved.validators,
+            tail_validators: saved.tail_validators,
+            graph: ::ReplyGraph::default(),
+            storage: None
+        }.with_graph()
+    }
+
+    /// Restores a `Thread` kept by a `Storage`, making its requests through
+    /// `client`.
+    pub fn from_stored(stored: ::StoredThread, client: ::Client) -> Thread {
+        let wants_update = !stored.expired && stored.topic.archived == 0;
+        let last_reply_no = stored.replies.last().map_or(0, |r| r.no);
+
+        Thread {
+            board_name: stored.board_name,
+            client: client,
+            topic: stored.topic,
+            replies: stored.replies,
+            changes: Vec::new(),
+            expired: stored.expired,
+            expired_at: stored.expired_at,
+            wants_update: wants_update,
+            last_reply_no: last_reply_no,
+            last_updated: None,
+            validators: None,
+            tail_validators: None,
+            graph: ::ReplyGraph::default(),
+            storage: None
+        }.with_graph()
+    }
+
+    /// Creates a new `Thread` from a `ThreadDeserializer`. Returns
+    /// `Error::EmptyThread` if it has no posts.
     pub fn from_deserializer(deserializer: ThreadDeserializer,
                              board_name: &str,
-                             client: Arc<Mutex<::Client>>) -> Thread {
-        let topic = deserializer.posts.first().unwrap().to_owned();
+                             client: ::Client) -> ::Result<Thread> {
+        let topic = match deserializer.posts.first() {
+            Some(topic) => topic.clone(),
+            None => return Err(::Error::EmptyThread)
+        };
 
-        Thread {
+        Ok(Thread {
             board_name: board_name.to_string(),
             client: client,
             topic: topic.clone(),
             replies: deserializer.posts.iter().skip(1).cloned().collect(),
+            changes: Vec::new(),
             expired: false,
-            wants_update: true,
+            expired_at: if topic.archived != 0 {
+                Some(UTC::now().timestamp())
+            } else {
+                None
+            },
+            wants_update: topic.archived == 0,
             last_reply_no: {
-                if !topic.last_replies.is_empty() {
+                if deserializer.posts.len() > 1 {
+                    deserializer.posts.last().unwrap().no
+                } else if !topic.last_replies.is_empty() {
                     topic.last_replies.last().unwrap().no
                 } else {
                     0
                 }
             },
-            last_updated: None
+            last_updated: None,
+            validators: None,
+            tail_validators: None,
+            graph: ::ReplyGraph::default(),
+            storage: None
+        }.with_graph())
+    }
+
+    /// Creates a new `Thread` from the preview of a thread on an index page:
+    /// the topic followed by its latest few replies.
+    ///
+    /// If replies were omitted from the preview, the previews are kept in the
+    /// topic's `last_replies` and the next `update` fetches the whole thread.
+    /// Returns `Error::EmptyThread` if the preview has no posts.
+    pub fn from_preview(deserializer: ThreadDeserializer,
+                        board_name: &str,
+                        client: ::Client) -> ::Result<Thread> {
+        let mut topic = match deserializer.posts.first() {
+            Some(topic) => topic.clone(),
+            None => return Err(::Error::EmptyThread)
+        };
+        let previews = &deserializer.posts[1..];
+
+        let mut thread = Thread::from_topic(topic.clone(), board_name, client);
+        if topic.omitted_posts == 0 {
+            thread.replies = previews.to_vec();
+            thread.last_reply_no = previews.last().map_or(0, |p| p.no);
+        } else {
+            topic.last_replies = previews.iter().map(::LastReply::from)
+                .collect();
+            // Make sure `Board::refresh_cache` sees the thread as modified.
+            topic.last_modified = 0;
+            thread.topic = topic;
+            thread.last_reply_no = 0;
         }
+        Ok(thread.with_graph())
+    }
+
+    /// Builds the thread's `ReplyGraph` from scratch.
+    fn with_graph(mut self) -> Thread {
+        self.graph = ::ReplyGraph::from_thread(&self);
+        self
     }
 
-    /// Updates a `Thread`, throttling updates by 10 second intervals and
-    /// using "If-Modified-Since".
-    pub fn update(&mut self) -> ::Result<()> {
-        if self.expired { return Ok(()) }
+    /// Merges the preview of the thread on an index page into it. The topic
+    /// is replaced and previewed replies that are newer than the known ones
+    /// are appended, as long as there is no gap between them. Otherwise the
+    /// previews are kept in the topic's `last_replies` until the next
+    /// `update`.
+    pub fn merge_preview(&mut self, posts: &[::Post]) {
+        let mut topic = match posts.first() {
+            Some(topic) => topic.clone(),
+            None => return
+        };
+        let previews = &posts[1..];
+        let known = self.replies.last().map_or(0, |r| r.no);
 
-        // Threads should be updated no faster than every 10 seconds.
-        if self.last_updated.is_some() {
-            let diff = UTC::now()
-                .signed_duration_since(self.last_updated.unwrap());
-            if diff < Duration::seconds(10) {
-                sleep(try!(diff.to_std()));
+        let complete = topic.omitted_posts == 0 ||
+            previews.first().map_or(true, |p| p.no <= known);
+        if complete {
+            for post in previews {
+                if post.no > known {
+                    self.graph.add_post(post);
+                    self.replies.push(post.clone());
+                }
+            }
+            if let Some(last) = self.replies.last() {
+                self.last_reply_no = last.no;
+            }
+        } else {
+            topic.last_replies = previews.iter().map(::LastReply::from)
+                .collect();
+            // Keep what we knew, so `Board::refresh_cache` still sees the
+            // thread as modified while replies are missing.
+            topic.last_modified = self.topic.last_modified;
+            self.wants_update = true;
+        }
+        if self.topic.com != topic.com {
+            self.graph.add_post(&topic);
+        }
+        self.topic = topic;
+    }
+
+    /// Updates a `Thread`, throttling updates by 10 second intervals (see
+    /// `ClientBuilder::thread_interval`) and using conditional requests.
+    /// Expired and archived threads never change, so they are not updated
+    /// and an empty `ThreadDelta` is returned.
+    ///
+    /// Threads whose topic has a `tail_size` are updated from their tail
+    /// JSON, which only holds the latest replies, unless there is a gap
+    /// between the tail and the replies we have.
+    ///
+    /// A thread with a `Storage` (see `set_storage`) is upserted into it
+    /// after every update that wasn't answered with Not Modified.
+    pub fn update(&mut self) -> ::Result<ThreadDelta> {
+        if self.expired || self.is_archived() {
+            return Ok(ThreadDelta::default())
+        }
+
+        let delta = try!(self.fetch_update());
+        self.store(&delta);
+        Ok(delta)
+    }
+
+    fn fetch_update(&mut self) -> ::Result<ThreadDelta> {
+        if self.uses_tail() {
+            let url = self.tail_url();
+            let res = try!(self.enqueue(&url, self.tail_validators.as_ref())
+                           .wait());
+            if let Some(delta) = try!(self.apply_tail(res)) {
+                return Ok(delta)
+            }
+        }
+
+        let url = self.api_url();
+        let res = try!(self.enqueue(&url, self.validators.as_ref()).wait());
+        self.apply_full(res)
+    }
+
+    /// Like `update`, but returns a future instead of blocking. The future
+    /// resolves to the updated thread and what changed; if the update fails
+    /// the thread is dropped, so keep a clone if you need it.
+    pub fn update_async(self) -> ::ApiFuture<(Thread, ThreadDelta)> {
+        if self.expired || self.is_archived() {
+            return Box::new(future::ok((self, ThreadDelta::default())))
+        }
+
+        let update: ::ApiFuture<(Thread, ThreadDelta)> = if self.uses_tail() {
+            let url = self.tail_url();
+            let ticket = self.enqueue(&url, self.tail_validators.as_ref());
+            Box::new(ticket.and_then(move |res| {
+                let mut thread = self;
+                match thread.apply_tail(res) {
+                    Ok(Some(delta)) => {
+                        Either::A(future::ok((thread, delta)))
+                    },
+                    Ok(None) => Either::B(thread.update_full_async()),
+                    Err(e) => Either::A(future::err(e))
+                }
+            }))
+        } else {
+            self.update_full_async()
+        };
+
+        Box::new(update.map(|(thread, delta)| {
+            thread.store(&delta);
+            (thread, delta)
+        }))
+    }
+
+    fn update_full_async(self) -> ::ApiFuture<(Thread, ThreadDelta)> {
+        let url = self.api_url();
+        let ticket = self.enqueue(&url, self.validators.as_ref());
+        Box::new(ticket.and_then(move |res| {
+            let mut thread = self;
+            let delta = try!(thread.apply_full(res));
+            Ok((thread, delta))
+        }))
+    }
+
+    /// Upserts the thread into `storage` after every update from now on.
+    /// Threads added to a `ThreadCache` with a storage get the cache's.
+    pub fn set_storage(&mut self, storage: Arc<::Storage>) {
+        self.storage = Some(storage);
+    }
+
+    /// Upserts the thread into its storage after an update, unless the
+    /// server answered Not Modified. A failure is only logged, as the
+    /// thread itself is up to date.
+    fn store(&self, delta: &ThreadDelta) {
+        if delta.not_modified {
+            return
+        }
+        if let Some(ref storage) = self.storage {
+            if let Err(e) = storage.store_thread(self) {
+                warn!("Failed to store thread {}: {}", self.topic.no, e);
             }
         }
+    }
 
-        let mut res = try!(self.client.lock().unwrap().get(
-                &format!("https://a.4cdn.org/{}/thread/{}.json",
-                         self.board_name, self.topic.no),
-                         self.topic.if_modified_since()));
+    /// Whether the thread should be updated from its tail JSON first.
+    fn uses_tail(&self) -> bool {
+        self.topic.tail_size > 0 && !self.replies.is_empty()
+    }
 
+    /// Applies the response to a request for the full thread JSON.
+    fn apply_full(&mut self, res: ::Response) -> ::Result<ThreadDelta> {
         self.last_updated = Some(UTC::now());
 
-        match *res.status() {
+        match res.status {
             StatusCode::Ok => {
                 self.wants_update = true;
-                let mut buf = String::new();
-                try!(res.read_to_string(&mut buf));
 
-                debug!("Got response: {}", buf);
+                debug!("Got response: {}", res.body);
 
+                let url = self.api_url();
                 let thread: ThreadDeserializer = try!(
-                    ::serde_json::from_str(&buf));
-                self.topic = thread.posts.first().unwrap().to_owned();
-
-                if self.topic.replies > 0 {
-                    if self.last_reply_no != 0 {
-                        for post in thread.posts.iter().skip(1) {
-                            if post.no > self.last_reply_no {
-                                self.replies.push(post.to_owned());
-                            }
-                        }
-                    } else {
-                        for post in thread.posts.iter().skip(1) {
-                            self.replies.push(post.to_owned());
-                        }
-                    }
-                    if self.replies.len() > 0 {
-                        self.last_reply_no = self.replies.last().unwrap().no;
-                    }
+                    ::error::from_json(&url, &res.body));
+                if thread.posts.is_empty() {
+                    return Err(::Error::MalformedResponse {
+                        url: url,
+                        path: "$.posts".to_string(),
+                        message: "thread has no posts".to_string()
+                    })
                 }
-                Ok(())
+                self.validators = ::Validators::from_response(&res);
+                Ok(self.reconcile(&thread.posts, 0))
             },
             StatusCode::NotModified => {
-                Ok(())
+                Ok(ThreadDelta { not_modified: true, ..Default::default() })
             },
             StatusCode::NotFound => {
                 self.expired = true;
+                self.expired_at = Some(UTC::now().timestamp());
                 self.wants_update = false;
-                // TODO: Delete from cache? If so, now?
-                // How long do we want to keep expired threads in cache?
-                Ok(())
+                // How long the thread is kept in the cache is up to the
+                // cache's `RetentionPolicy`.
+                Ok(ThreadDelta {
+                    events: vec![ThreadEvent::Expired],
+                    ..Default::default()
+                })
+            }
+            _ => Err(::Error::from_response(&self.api_url(), &res))
+        }
+    }
+
+    /// Applies the response to a request for the tail JSON. Returns None if
+    /// the thread needs a full update instead, because the tail doesn't
+    /// reach back to the replies we have or the board doesn't provide one.
+    fn apply_tail(&mut self, res: ::Response)
+        -> ::Result<Option<ThreadDelta>> {
+        match res.status {
+            StatusCode::Ok => {
+                let tail: ThreadDeserializer = try!(
+                    ::error::from_json(&self.tail_url(), &res.body));
+                let overlaps = tail.posts.get(1)
+                    .map_or(false, |p| p.no <= self.last_reply_no);
+                if !overlaps {
+                    debug!("Tail of thread {} has a gap, updating fully",
+                           self.topic.no);
+                    return Ok(None)
+                }
+
+                self.last_updated = Some(UTC::now());
+                self.wants_update = true;
+                self.tail_validators = ::Validators::from_response(&res);
+                let tail_start = tail.posts[1].no;
+                Ok(Some(self.reconcile(&tail.posts, tail_start)))
+            },
+            StatusCode::NotModified => {
+                self.last_updated = Some(UTC::now());
+                Ok(Some(ThreadDelta {
+                    not_modified: true,
+                    ..Default::default()
+                }))
+            },
+            // Either the board has no tails or the thread is gone. The full
+            // update will tell which.
+            StatusCode::NotFound => Ok(None),
+            _ => Err(::Error::from_response(&self.tail_url(), &res))
+        }
+    }
+
+    /// Enqueues a GET request to one of the thread's urls, conditional on
+    /// `validators`. The scheduler makes sure the thread isn't requested more
+    /// often than the thread interval allows, through any of its urls.
+    fn enqueue(&self, url: &str, validators: Option<&::Validators>)
+        -> ::Ticket {
+        self.client.enqueue(::Request::new(url)
+                            .min_interval(self.client.thread_interval())
+                            .resource(&self.api_url())
+                            .conditional(validators))
+    }
+
+    /// Reconciles the thread with `posts`, the topic followed by every reply
+    /// numbered `from_no` or higher as the server has them now. `posts` must
+    /// not be empty.
+    ///
+    /// Replies we have in that range that are missing from `posts` were
+    /// deleted; they are kept with `deleted_at` set. Changes to the fields of
+    /// the topic and the remaining replies are recorded in `changes`, and
+    /// new replies are added. Returns everything that happened.
+    fn reconcile(&mut self, posts: &[::Post], from_no: u64) -> ThreadDelta {
+        let now = UTC::now().timestamp();
+        let mut delta = ThreadDelta::default();
+
+        let topic = posts.first().unwrap().to_owned();
+        let topic_changes = self.topic.changes(&topic, now);
+        delta.events.extend(topic_changes.iter()
+                            .filter_map(ThreadEvent::from_change));
+        delta.changes.extend(topic_changes);
+        if self.topic.com != topic.com {
+            self.graph.add_post(&topic);
+        }
+        self.topic = topic;
+        if self.is_archived() {
+            self.wants_update = false;
+            if self.expired_at.is_none() {
+                self.expired_at = Some(now);
             }
-            _ => Err(::Error::UnexpectedResponse)
         }
+
+        let fresh = posts.iter().skip(1)
+            .map(|p| (p.no, p))
+            .collect::<HashMap<u64, &::Post>>();
+
+        for reply in self.replies.iter_mut().filter(|r| r.no >= from_no) {
+            match fresh.get(&reply.no) {
+                Some(&post) => {
+                    delta.changes.extend(reply.changes(post, now));
+                    if reply.com != post.com || reply.is_deleted() {
+                        self.graph.add_post(post);
+                    }
+                    *reply = post.to_owned();
+                },
+                None => {
+                    if reply.deleted_at.is_none() {
+                        debug!("Post {} in thread {} was deleted",
+                               reply.no, self.topic.no);
+                        reply.deleted_at = Some(now);
+                        delta.deleted.push(reply.no);
+                        self.graph.mark_deleted(reply.no);
+                    }
+                }
+            }
+        }
+
+        let known = self.replies.iter()
+            .map(|r| r.no)
+            .collect::<HashSet<u64>>();
+        for post in posts.iter().skip(1) {
+            if !known.contains(&post.no) {
+                self.graph.add_post(post);
+                self.replies.push(post.to_owned());
+                delta.new_replies.push(post.to_owned());
+            }
+        }
+        self.replies.sort_by_key(|r| r.no);
+
+        if let Some(last) = self.replies.last() {
+            self.last_reply_no = last.no;
+        }
+
+        delta.file_deleted = delta.changes.iter()
+            .filter(|c| c.field == ::ChangeField::FileDeleted && c.new == "1")
+            .map(|c| c.no)
+            .collect();
+        self.changes.extend(delta.changes.iter().cloned());
+        delta
     }
 
     pub fn is_match(&self, regex: &::regex::Regex) -> bool {
         self.topic.is_match(regex)
     }
 
+    /// When the thread was last updated, if it ever was.
+    pub fn last_updated(&self) -> Option<DateTime<UTC>> {
+        self.last_updated
+    }
+
+    /// Whether the thread was pruned, ie. it 404'd. An expired thread is
+    /// gone for good.
     pub fn is_expired(&self) -> bool {
         self.expired
     }
 
+    /// Whether the thread was moved to the board's archive. An archived
+    /// thread can still be fetched but will never change again.
+    pub fn is_archived(&self) -> bool {
+        self.topic.archived == 1
+    }
+
+    /// Renders the topic and every reply with `Post::render`, separated by
+    /// blank lines.
+    pub fn render(&self, format: ::Format) -> String {
+        ::render::thread(self, format)
+    }
+
+    /// Which posts of the thread quote which, kept up to date by `update`.
+    pub fn reply_graph(&self) -> &::ReplyGraph {
+        &self.graph
+    }
+
+    /// The replies that were deleted since the thread was first fetched.
+    pub fn deleted_replies(&self) -> Vec<&::Post> {
+        self.replies.iter().filter(|r| r.is_deleted()).collect()
+    }
+
+    /// When the thread was archived, if it was.
+    pub fn archived_on(&self) -> Option<DateTime<UTC>> {
+        if !self.is_archived() {
+            return None
+        }
+        Some(DateTime::<UTC>::from_utc(
+            NaiveDateTime::from_timestamp(self.topic.archived_on as i64, 0),
+            UTC))
+    }
+
     pub fn wants_update(&self) -> bool {
         self.wants_update
     }
@@ -153,19 +623,42 @@ impl Thread {
     }
 
     pub fn url(&self) -> String {
-        format!("https://boards.4chan.org/{}/thread/{}",
+        format!("{}/{}/thread/{}", self.client.web_url(),
+                &self.board_name, &self.topic.no)
+    }
+
+    /// The url of the thread's JSON.
+    pub fn api_url(&self) -> String {
+        format!("{}/{}/thread/{}.json", self.client.api_url(),
+                &self.board_name, &self.topic.no)
+    }
+
+    /// The url of the thread's tail JSON, which holds only the topic and the
+    /// latest `tail_size` replies.
+    pub fn tail_url(&self) -> String {
+        format!("{}/{}/thread/{}-tail.json",
+                self.client.api_url(),
                 &self.board_name, &self.topic.no)
     }
 
+    /// The approximate memory use of the thread in bytes (see
+    /// `Post::approx_size`).
+    pub fn approx_size(&self) -> usize {
+        iter::once(&self.topic).chain(&self.replies)
+            .map(|p| p.approx_size())
+            .sum()
+    }
+
     /// Get a `Vec` of all the image urls in the thread.
     pub fn image_urls(&self) -> Vec<String> {
+        let image_url = self.client.image_url().to_string();
         let mut images: Vec<String> = Vec::new();
-        let topic_img = self.topic.image_url(&self.board_name);
+        let topic_img = self.topic.image_url(&image_url, &self.board_name);
         if topic_img.is_some() {
             images.push(topic_img.unwrap());
         }
         for reply in &self.replies {
-            match reply.image_url(&self.board_name) {
+            match reply.image_url(&image_url, &self.board_name) {
                 Some(i) => images.push(i),
                 None => (),
             }
@@ -174,28 +667,269 @@ impl Thread {
     }
 }
 
+/// A `ThreadDelta` is everything a `Thread::update` noticed.
+#[derive(Clone, Debug, Default)]
+pub struct ThreadDelta {
+    // Replies that weren't known before, oldest first.
+    pub new_replies: Vec<::Post>,
+    // Numbers of the replies that were deleted.
+    pub deleted: Vec<u64>,
+    // Numbers of the posts whose file was deleted.
+    pub file_deleted: Vec<u64>,
+    // Changes to the fields of the topic and replies.
+    pub changes: Vec<::PostChange>,
+    // Changes to the state of the thread.
+    pub events: Vec<ThreadEvent>,
+    // Whether the server answered Not Modified.
+    pub not_modified: bool
+}
+
+impl ThreadDelta {
+    /// Whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.new_replies.is_empty() &&
+            self.deleted.is_empty() &&
+            self.changes.is_empty() &&
+            self.events.is_empty()
+    }
+
+    /// Whether the thread won't change anymore because it expired or was
+    /// archived.
+    pub fn is_final(&self) -> bool {
+        self.events.iter().any(|e| {
+            *e == ThreadEvent::Expired || *e == ThreadEvent::Archived
+        })
+    }
+}
+
+/// A `ThreadEvent` is a change to the state of a thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThreadEvent {
+    Stickied,
+    Unstickied,
+    Closed,
+    Reopened,
+    Archived,
+    HitBumpLimit,
+    HitImageLimit,
+    // The thread 404'd.
+    Expired
+}
+
+impl ThreadEvent {
+    /// The event a change to the topic stands for, if any.
+    fn from_change(change: &::PostChange) -> Option<ThreadEvent> {
+        let set = change.new == "1";
+        match change.field {
+            ::ChangeField::Sticky if set => Some(ThreadEvent::Stickied),
+            ::ChangeField::Sticky => Some(ThreadEvent::Unstickied),
+            ::ChangeField::Closed if set => Some(ThreadEvent::Closed),
+            ::ChangeField::Closed => Some(ThreadEvent::Reopened),
+            ::ChangeField::Archived if set => Some(ThreadEvent::Archived),
+            ::ChangeField::Bumplimit if set => {
+                Some(ThreadEvent::HitBumpLimit)
+            },
+            ::ChangeField::Imagelimit if set => {
+                Some(ThreadEvent::HitImageLimit)
+            },
+            ::ChangeField::Archived |
+            ::ChangeField::Bumplimit |
+            ::ChangeField::Imagelimit |
+            ::ChangeField::Name |
+            ::ChangeField::Trip |
+            ::ChangeField::Capcode |
+            ::ChangeField::Sub |
+            ::ChangeField::Com |
+            ::ChangeField::Filename |
+            ::ChangeField::FileDeleted |
+            ::ChangeField::Spoiler => None
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ThreadDeserializer {
     pub posts: Vec<::Post>
 }
 
-/// A `ThreadCache` is an abstraction around a `HashMap<u64, Thread>`.
+/// A `RetentionPolicy` limits what a `ThreadCache` keeps. When the cache
+/// grows past a limit, the least recently used threads are evicted first.
+/// Pinned threads are never evicted. Every limit is off by default.
+#[derive(Clone, Debug, Default)]
+pub struct RetentionPolicy {
+    pub max_threads: Option<usize>,
+    // The maximum number of posts, counting topics and replies.
+    pub max_posts: Option<usize>,
+    // The maximum approximate memory use of the threads (see
+    // `Thread::approx_size`).
+    pub max_bytes: Option<usize>,
+    // How long expired and archived threads are kept after that was
+    // noticed. Without one, expired threads are removed as soon as they are
+    // retired (see `ThreadCache::retire`) and archived threads are kept
+    // until evicted by a limit.
+    pub expired_ttl: Option<Duration>
+}
+
+impl RetentionPolicy {
+    /// Creates a new `RetentionPolicy` without any limits.
+    pub fn new() -> RetentionPolicy {
+        RetentionPolicy::default()
+    }
+
+    pub fn max_threads(mut self, threads: usize) -> RetentionPolicy {
+        self.max_threads = Some(threads);
+        self
+    }
+
+    pub fn max_posts(mut self, posts: usize) -> RetentionPolicy {
+        self.max_posts = Some(posts);
+        self
+    }
+
+    pub fn max_bytes(mut self, bytes: usize) -> RetentionPolicy {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    pub fn expired_ttl(mut self, ttl: Duration) -> RetentionPolicy {
+        self.expired_ttl = Some(ttl);
+        self
+    }
+}
+
+/// How many threads a `ThreadCache` has evicted, and why.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvictionStats {
+    // Evicted to stay within a limit.
+    pub lru: u64,
+    // Evicted because they expired or were archived longer ago than the TTL.
+    pub ttl: u64
+}
+
+/// A `ThreadCache` is an abstraction around a `HashMap<u64, Thread>`,
+/// keeping it within its `RetentionPolicy`. Threads are evicted when one is
+/// inserted, or on `evict`.
+///
+/// Only `get`, `insert` and `replace` count as access to a thread. Going
+/// through `threads` directly doesn't, and changes made there aren't counted
+/// against the limits; use `replace` instead.
+///
+/// A cache can be backed by a `Storage` with `set_storage`. Its threads
+/// then upsert themselves into it on every update, and it keeps the threads
+/// it evicts until `take_evicted`, so they can be stored without holding
+/// the cache's lock; `Board` does this, and restores stored threads that
+/// aren't cached on `get_thread`.
 #[derive(Debug)]
 pub struct ThreadCache {
-    pub threads: HashMap<u64, Thread>
+    pub threads: HashMap<u64, Thread>,
+    policy: RetentionPolicy,
+    pinned: HashSet<u64>,
+    // The value of `clock` when each thread was last accessed.
+    accessed: HashMap<u64, Cell<u64>>,
+    clock: Cell<u64>,
+    // The number of posts and approximate size of each thread as it was
+    // added, and their totals.
+    sizes: HashMap<u64, (usize, usize)>,
+    posts: usize,
+    bytes: usize,
+    stats: EvictionStats,
+    storage: Option<Arc<::Storage>>,
+    // Threads evicted since the last `take_evicted`, only kept with a
+    // storage so they can be stored outside the cache's lock.
+    evicted: Vec<Thread>
 }
 
 impl ThreadCache {
     pub fn new() -> ThreadCache {
-        ThreadCache { threads: HashMap::new() }
+        ThreadCache::with_policy(RetentionPolicy::default())
+    }
+
+    /// Creates a new `ThreadCache` keeping within `policy`.
+    pub fn with_policy(policy: RetentionPolicy) -> ThreadCache {
+        ThreadCache {
+            threads: HashMap::new(),
+            policy: policy,
+            pinned: HashSet::new(),
+            accessed: HashMap::new(),
+            clock: Cell::new(0),
+            sizes: HashMap::new(),
+            posts: 0,
+            bytes: 0,
+            stats: EvictionStats::default(),
+            storage: None,
+            evicted: Vec::new()
+        }
+    }
+
+    pub fn policy(&self) -> &RetentionPolicy {
+        &self.policy
+    }
+
+    /// Replaces the `RetentionPolicy` and evicts what it doesn't allow.
+    pub fn set_policy(&mut self, policy: RetentionPolicy) -> Vec<u64> {
+        self.policy = policy;
+        self.evict()
+    }
+
+    /// Backs the cache with `storage`. Cached threads, and those added
+    /// from now on, are upserted into it on every update (see
+    /// `Thread::set_storage`), as are clones of them.
+    pub fn set_storage(&mut self, storage: Arc<::Storage>) {
+        for thread in self.threads.values_mut() {
+            thread.set_storage(storage.clone());
+        }
+        self.storage = Some(storage);
+    }
+
+    pub fn storage(&self) -> Option<Arc<::Storage>> {
+        self.storage.clone()
+    }
+
+    /// Takes the threads evicted since the last call, to be stored. Always
+    /// empty without a storage.
+    pub fn take_evicted(&mut self) -> Vec<Thread> {
+        mem::replace(&mut self.evicted, Vec::new())
     }
 
     pub fn get(&self, thread_no: u64) -> Option<&Thread> {
-        self.threads.get(&thread_no)
+        let thread = self.threads.get(&thread_no);
+        if thread.is_some() {
+            self.touch(thread_no);
+        }
+        thread
     }
 
-    pub fn insert(&mut self, thread: Thread) {
-        self.threads.entry(thread.topic.no).or_insert(thread);
+    /// Adds a thread to the cache, unless it is cached already.
+    pub fn insert(&mut self, mut thread: Thread) {
+        let thread_no = thread.topic.no;
+        if !self.threads.contains_key(&thread_no) {
+            if let Some(ref storage) = self.storage {
+                thread.set_storage(storage.clone());
+            }
+            self.count(&thread);
+            self.threads.insert(thread_no, thread);
+            self.accessed.insert(thread_no, Cell::new(0));
+        }
+        self.touch(thread_no);
+        self.evict();
+    }
+
+    /// Replaces a cached thread with an updated copy of it, eg. one updated
+    /// without holding the cache's lock. A thread that is no longer cached,
+    /// because it was evicted or removed in the meantime, isn't added back.
+    pub fn replace(&mut self, mut thread: Thread) {
+        let thread_no = thread.topic.no;
+        if !self.threads.contains_key(&thread_no) {
+            return
+        }
+        if let Some(ref storage) = self.storage {
+            thread.set_storage(storage.clone());
+        }
+        self.uncount(thread_no);
+        self.count(&thread);
+        self.threads.insert(thread_no, thread);
+        self.touch(thread_no);
+        self.evict();
     }
 
     pub fn contains(&self, thread_no: u64) -> bool {
@@ -203,7 +937,190 @@ impl ThreadCache {
     }
 
     pub fn remove(&mut self, thread_no: u64) {
-        self.threads.remove(&thread_no);
+        self.take(thread_no);
+    }
+
+    /// Removes an expired thread, unless the policy keeps expired threads
+    /// for a while, in which case `evict` removes it once its time is up.
+    pub fn retire(&mut self, thread_no: u64) {
+        if self.policy.expired_ttl.is_none() {
+            self.remove(thread_no);
+        }
+    }
+
+    /// Pins a thread, eg. one that is being watched, so that it is never
+    /// evicted.
+    pub fn pin(&mut self, thread_no: u64) {
+        self.pinned.insert(thread_no);
+    }
+
+    pub fn unpin(&mut self, thread_no: u64) {
+        self.pinned.remove(&thread_no);
+    }
+
+    pub fn is_pinned(&self, thread_no: u64) -> bool {
+        self.pinned.contains(&thread_no)
+    }
+
+    /// How many threads have been evicted so far.
+    pub fn stats(&self) -> EvictionStats {
+        self.stats
+    }
+
+    /// Saves every thread in the cache to `path` in a versioned json format,
+    /// along with which threads are pinned. Threads keep the validators of
+    /// their last responses, so they resume updating with conditional
+    /// requests once restored with `load`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> ::Result<()> {
+        self.save_with(path, Vec::new())
+    }
+
+    /// Like `save`, also saving `validators` of other urls, eg. the catalog
+    /// of the threads' board.
+    pub fn save_with<P: AsRef<Path>>(&self, path: P,
+                                     validators: Vec<(String, ::Validators)>)
+        -> ::Result<()> {
+        let file = CacheFile {
+            version: CACHE_FORMAT_VERSION,
+            threads: self.threads.values().collect(),
+            pinned: self.pinned.iter().cloned().collect(),
+            validators: validators
+        };
+        let json = try!(::serde_json::to_string(&file));
+
+        // Write to a temporary file first, so a crash can't leave a
+        // half-written cache behind.
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        try!(try!(File::create(&tmp)).write_all(json.as_bytes()));
+        try!(fs::rename(&tmp, path));
+        Ok(())
+    }
+
+    /// Loads the threads saved to `path` with `save` into the cache,
+    /// replacing the cached threads with the same number, which stay pinned
+    /// if they were. The threads make their requests through `client`.
+    ///
+    /// Returns the number of threads loaded.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P, client: &::Client)
+        -> ::Result<usize> {
+        self.load_with(path, client).map(|(loaded, _)| loaded)
+    }
+
+    /// Like `load`, also returning the validators of other urls saved with
+    /// `save_with`.
+    pub fn load_with<P: AsRef<Path>>(&mut self, path: P, client: &::Client)
+        -> ::Result<(usize, Vec<(String, ::Validators)>)> {
+        let mut buf = String::new();
+        try!(try!(File::open(path.as_ref())).read_to_string(&mut buf));
+
+        let version: SavedVersion = try!(::serde_json::from_str(&buf));
+        if version.version != CACHE_FORMAT_VERSION {
+            return Err(::Error::CacheVersion(version.version))
+        }
+        let saved: SavedCache = try!(::serde_json::from_str(&buf));
+
+        // Pin first, so pinned threads aren't evicted while loading.
+        for thread_no in saved.pinned {
+            self.pin(thread_no);
+        }
+        let loaded = saved.threads.len();
+        for saved_thread in saved.threads {
+            let thread = Thread::from_saved(saved_thread, client.clone());
+            if self.contains(thread.topic.no) {
+                self.replace(thread);
+            } else {
+                self.insert(thread);
+            }
+        }
+
+        Ok((loaded, saved.validators))
+    }
+
+    /// Evicts the threads that expired longer ago than the TTL, then the
+    /// least recently used threads until the cache is within its limits.
+    /// Returns the numbers of the evicted threads.
+    pub fn evict(&mut self) -> Vec<u64> {
+        let mut evicted = Vec::new();
+
+        if let Some(ttl) = self.policy.expired_ttl {
+            let deadline = UTC::now().timestamp() - ttl.as_secs() as i64;
+            let expired = self.threads.values()
+                .filter(|t| !self.pinned.contains(&t.topic.no))
+                .filter(|t| t.expired_at.map_or(false, |at| at <= deadline))
+                .map(|t| t.topic.no)
+                .collect::<Vec<u64>>();
+            for thread_no in expired {
+                self.evict_one(thread_no);
+                self.stats.ttl += 1;
+                evicted.push(thread_no);
+            }
+        }
+
+        while self.over_limits() {
+            let lru = self.threads.keys()
+                .filter(|no| !self.pinned.contains(*no))
+                .min_by_key(|no| self.accessed.get(*no).map_or(0, |c| c.get()))
+                .cloned();
+            let thread_no = match lru {
+                Some(thread_no) => thread_no,
+                None => break
+            };
+
+            debug!("Evicting thread {} from the cache", thread_no);
+            self.evict_one(thread_no);
+            self.stats.lru += 1;
+            evicted.push(thread_no);
+        }
+
+        evicted
+    }
+
+    fn over_limits(&self) -> bool {
+        let threads = self.threads.len();
+        self.policy.max_threads.map_or(false, |max| threads > max) ||
+            self.policy.max_posts.map_or(false, |max| self.posts > max) ||
+            self.policy.max_bytes.map_or(false, |max| self.bytes > max)
+    }
+
+    /// Adds a thread's posts and size to the totals.
+    fn count(&mut self, thread: &Thread) {
+        let size = (thread.replies.len() + 1, thread.approx_size());
+        self.posts += size.0;
+        self.bytes += size.1;
+        self.sizes.insert(thread.topic.no, size);
+    }
+
+    /// Takes a thread's posts and size, as they were added, off the totals.
+    fn uncount(&mut self, thread_no: u64) {
+        if let Some((posts, bytes)) = self.sizes.remove(&thread_no) {
+            self.posts -= posts;
+            self.bytes -= bytes;
+        }
+    }
+
+    fn take(&mut self, thread_no: u64) -> Option<Thread> {
+        self.accessed.remove(&thread_no);
+        self.pinned.remove(&thread_no);
+        self.uncount(thread_no);
+        self.threads.remove(&thread_no)
+    }
+
+    // Removes a thread, keeping it to be stored if there's a storage.
+    fn evict_one(&mut self, thread_no: u64) {
+        if let Some(thread) = self.take(thread_no) {
+            if self.storage.is_some() {
+                self.evicted.push(thread);
+            }
+        }
+    }
+
+    fn touch(&self, thread_no: u64) {
+        let clock = self.clock.get() + 1;
+        self.clock.set(clock);
+        if let Some(accessed) = self.accessed.get(&thread_no) {
+            accessed.set(clock);
+        }
     }
 }
 
@@ -222,3 +1139,279 @@ impl fmt::Display for ThreadCache {
                .join(", "))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use chrono::UTC;
+    use futures::Future;
+    use futures::future::join_all;
+    use reqwest::StatusCode;
+
+    use testing;
+
+    #[test]
+    fn update_expires_on_not_found() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+
+        let mut thread = g.get_thread(100).unwrap();
+        assert_eq!(1, thread.replies.len());
+
+        transport.insert(testing::THREAD_URL,
+                         ::Response::new(StatusCode::NotFound, ""));
+        let delta = thread.update().unwrap();
+        assert_eq!(vec![::ThreadEvent::Expired], delta.events);
+        assert!(delta.is_final());
+        assert!(thread.is_expired());
+        assert!(!thread.wants_update());
+    }
+
+    #[test]
+    fn update_from_tail() {
+        let transport = testing::transport();
+        transport.insert_ok(testing::THREAD_URL, r#"{"posts": [
+            {"no": 100, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+             "time": 1492218205, "replies": 2, "tail_size": 2},
+            {"no": 101, "resto": 100, "now": "04/15/17(Sat)01:05:00",
+             "time": 1492218300},
+            {"no": 102, "resto": 100, "now": "04/15/17(Sat)01:05:10",
+             "time": 1492218310}
+        ]}"#);
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let mut thread = g.get_thread(100).unwrap();
+        assert_eq!(2, thread.replies.len());
+
+        // The tail overlaps the replies we have.
+        transport.insert_ok("https://a.4cdn.org/g/thread/100-tail.json",
+                            r#"{"posts": [
+            {"no": 100, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+             "time": 1492218205, "replies": 3, "tail_size": 2},
+            {"no": 102, "resto": 100, "now": "04/15/17(Sat)01:05:10",
+             "time": 1492218310},
+            {"no": 103, "resto": 100, "now": "04/15/17(Sat)01:05:20",
+             "time": 1492218320}
+        ]}"#);
+        thread.update().unwrap();
+        assert_eq!(vec![101, 102, 103],
+                   thread.replies.iter().map(|r| r.no).collect::<Vec<u64>>());
+        assert!(transport.requests().last().unwrap().0.ends_with("-tail.json"));
+
+        // The tail has a gap, so the full thread is fetched.
+        transport.insert_ok("https://a.4cdn.org/g/thread/100-tail.json",
+                            r#"{"posts": [
+            {"no": 100, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+             "time": 1492218205, "replies": 5, "tail_size": 2},
+            {"no": 105, "resto": 100, "now": "04/15/17(Sat)01:05:40",
+             "time": 1492218340}
+        ]}"#);
+        transport.insert_ok(testing::THREAD_URL, r#"{"posts": [
+            {"no": 100, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+             "time": 1492218205, "replies": 5, "tail_size": 2},
+            {"no": 104, "resto": 100, "now": "04/15/17(Sat)01:05:30",
+             "time": 1492218330},
+            {"no": 105, "resto": 100, "now": "04/15/17(Sat)01:05:40",
+             "time": 1492218340}
+        ]}"#);
+        thread.update().unwrap();
+        assert_eq!(vec![101, 102, 103, 104, 105],
+                   thread.replies.iter().map(|r| r.no).collect::<Vec<u64>>());
+        assert_eq!(testing::THREAD_URL, transport.requests().last().unwrap().0);
+    }
+
+    #[test]
+    fn update_detects_deletions_and_changes() {
+        let transport = testing::transport();
+        transport.insert_ok(testing::THREAD_URL, r#"{"posts": [
+            {"no": 100, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+             "time": 1492218205, "replies": 2},
+            {"no": 101, "resto": 100, "now": "04/15/17(Sat)01:05:00",
+             "time": 1492218300, "filename": "a", "ext": ".png"},
+            {"no": 102, "resto": 100, "now": "04/15/17(Sat)01:05:10",
+             "time": 1492218310}
+        ]}"#);
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let mut thread = g.get_thread(100).unwrap();
+
+        transport.insert_ok(testing::THREAD_URL, r#"{"posts": [
+            {"no": 100, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+             "time": 1492218205, "replies": 2, "sticky": 1},
+            {"no": 101, "resto": 100, "now": "04/15/17(Sat)01:05:00",
+             "time": 1492218300, "filename": "a", "ext": ".png",
+             "file_deleted": 1},
+            {"no": 103, "resto": 100, "now": "04/15/17(Sat)01:05:20",
+             "time": 1492218320}
+        ]}"#);
+        let delta = thread.update().unwrap();
+        assert_eq!(vec![103], delta.new_replies.iter()
+                   .map(|r| r.no).collect::<Vec<u64>>());
+        assert_eq!(vec![102], delta.deleted);
+        assert_eq!(vec![101], delta.file_deleted);
+        assert_eq!(vec![::ThreadEvent::Stickied], delta.events);
+        assert!(!delta.not_modified);
+
+        transport.insert(testing::THREAD_URL,
+                         ::Response::new(StatusCode::NotModified, ""));
+        let delta = thread.update().unwrap();
+        assert!(delta.not_modified);
+        assert!(delta.is_empty());
+
+        assert_eq!(vec![101, 102, 103],
+                   thread.replies.iter().map(|r| r.no).collect::<Vec<u64>>());
+        assert_eq!(vec![102], thread.deleted_replies().iter()
+                   .map(|r| r.no).collect::<Vec<u64>>());
+        assert_eq!(2, thread.changes.len());
+        assert_eq!((100, ::ChangeField::Sticky),
+                   (thread.changes[0].no, thread.changes[0].field));
+        assert_eq!((101, ::ChangeField::FileDeleted),
+                   (thread.changes[1].no, thread.changes[1].field));
+        assert_eq!("1", thread.changes[1].new);
+    }
+
+    #[test]
+    fn update_async() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let _ = g.catalog().unwrap();
+
+        let threads = g.thread_cache.lock().unwrap().threads.values()
+            .cloned()
+            .collect::<Vec<::Thread>>();
+        let updated = join_all(threads.into_iter().map(|t| t.update_async()))
+            .wait()
+            .unwrap();
+
+        for (thread, delta) in updated {
+            match thread.topic.no {
+                100 => assert_eq!(1, delta.new_replies.len()),
+                200 => assert!(thread.is_expired()),
+                no => panic!("unexpected thread {}", no)
+            }
+        }
+    }
+
+    #[test]
+    fn cache_retention() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let _ = g.catalog().unwrap();
+        let (a, b) = {
+            let cache = g.thread_cache.lock().unwrap();
+            (cache.get(100).unwrap().clone(), cache.get(200).unwrap().clone())
+        };
+
+        let mut cache = ::ThreadCache::with_policy(
+            ::RetentionPolicy::new().max_threads(1));
+        cache.insert(a.clone());
+        cache.pin(100);
+        cache.insert(b.clone());
+        assert!(cache.contains(100));
+        assert!(!cache.contains(200));
+
+        cache.unpin(100);
+        cache.insert(b.clone());
+        assert!(!cache.contains(100));
+        assert!(cache.contains(200));
+        assert_eq!(::EvictionStats { lru: 2, ttl: 0 }, cache.stats());
+
+        let mut cache = ::ThreadCache::with_policy(
+            ::RetentionPolicy::new().expired_ttl(Duration::from_secs(60)));
+        let mut expired = a.clone();
+        expired.expired = true;
+        expired.expired_at = Some(UTC::now().timestamp());
+        cache.insert(expired.clone());
+        cache.retire(100);
+        assert!(cache.contains(100));
+
+        expired.topic.no = 300;
+        expired.expired_at = Some(0);
+        cache.insert(expired);
+        assert!(!cache.contains(300));
+        assert_eq!(1, cache.stats().ttl);
+    }
+
+    #[test]
+    fn retire_without_ttl() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let _ = g.catalog().unwrap();
+        let mut archived = g.thread_cache.lock().unwrap().get(100).unwrap()
+            .clone();
+        let mut expired = archived.clone();
+        expired.topic.no = 300;
+        expired.expired = true;
+        expired.expired_at = Some(0);
+        archived.topic.archived = 1;
+        archived.expired_at = Some(0);
+
+        let mut cache = ::ThreadCache::new();
+        cache.insert(expired);
+        cache.insert(archived);
+        cache.retire(300);
+        assert!(!cache.contains(300));
+        assert!(cache.contains(100));
+        assert!(cache.evict().is_empty());
+        assert_eq!(::EvictionStats::default(), cache.stats());
+    }
+
+    #[test]
+    fn save_and_load_cache() {
+        let tmp = testing::TempDir::new("thread-cache");
+        let path = tmp.join("cache.json");
+        let mut res = ::Response::new(StatusCode::Ok, testing::THREAD);
+        res.headers.set_raw(
+            "Last-Modified", vec![b"Sat, 15 Apr 2017 01:05:00 GMT".to_vec()]);
+        let transport = testing::transport();
+        transport.insert(testing::THREAD_URL, res);
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        g.get_thread(100).unwrap();
+        // Updating records the thread's Last-Modified.
+        g.get_thread(100).unwrap();
+        g.thread_cache.lock().unwrap().pin(100);
+        g.save_cache(&path).unwrap();
+
+        // A new process loads the cache and picks up where it left off.
+        let transport = testing::transport();
+        transport.insert(testing::THREAD_URL,
+                         ::Response::new(StatusCode::NotModified, ""));
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        assert_eq!(1, g.load_cache(&path).unwrap());
+
+        let mut cache = g.thread_cache.lock().unwrap();
+        assert!(cache.is_pinned(100));
+        let thread = cache.threads.get_mut(&100).unwrap();
+        assert_eq!(1, thread.replies.len());
+        thread.update().unwrap();
+        assert_eq!(Some(&::IfModifiedSince(
+            "Sat, 15 Apr 2017 01:05:00 GMT".to_string())),
+                   transport.requests().last().unwrap()
+                   .1.get::<::IfModifiedSince>());
+    }
+
+    #[test]
+    fn load_cache_keeps_pins() {
+        let tmp = testing::TempDir::new("thread-cache-pins");
+        let path = tmp.join("cache.json");
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let _ = g.catalog().unwrap();
+        g.thread_cache.lock().unwrap().pin(100);
+        g.save_cache(&path).unwrap();
+
+        // Pinned threads aren't evicted while loading.
+        let mut cache = ::ThreadCache::with_policy(
+            ::RetentionPolicy::new().max_threads(1));
+        assert_eq!(2, cache.load(&path, &g.client).unwrap());
+        assert!(cache.contains(100));
+        assert!(!cache.contains(200));
+
+        // Cached threads that are replaced stay pinned.
+        let mut cache = ::ThreadCache::new();
+        cache.insert(g.thread_cache.lock().unwrap().get(200).unwrap().clone());
+        cache.pin(200);
+        cache.load(&path, &g.client).unwrap();
+        assert!(cache.is_pinned(200));
+        assert!(cache.is_pinned(100));
+    }
+}
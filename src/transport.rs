@@ -0,0 +1,329 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::UTC;
+use reqwest::StatusCode;
+use reqwest::header::Headers;
+
+/// A `Transport` performs the actual HTTP GET requests on behalf of a
+/// `Client`. The default is `HttpTransport`, which goes to the network.
+/// Swap it for a `MemoryTransport` to run without one.
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Makes a GET request to the url with the given headers.
+    fn get(&self, url: &str, headers: Headers) -> ::Result<Response>;
+}
+
+/// A fully read HTTP response.
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: String
+}
+
+impl Response {
+    /// Creates a new `Response` with no headers.
+    pub fn new(status: StatusCode, body: &str) -> Response {
+        Response {
+            status: status,
+            headers: Headers::new(),
+            body: body.to_string()
+        }
+    }
+
+    /// Returns the value of a header as a string, if it is present.
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.headers.get_raw(name)
+            .and_then(|values| values.first())
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+    }
+}
+
+/// A `Transport` backed by a `reqwest::Client`.
+#[derive(Debug)]
+pub struct HttpTransport {
+    reqwest_client: ::reqwest::Client
+}
+
+impl HttpTransport {
+    /// Creates a new `HttpTransport`.
+    pub fn new() -> ::Result<HttpTransport> {
+        Ok(HttpTransport { reqwest_client: try!(::reqwest::Client::new()) })
+    }
+
+    /// Sets the timeout of every request.
+    pub fn timeout(&mut self, timeout: Duration) {
+        self.reqwest_client.timeout(timeout);
+    }
+}
+
+impl Transport for HttpTransport {
+    fn get(&self, url: &str, headers: Headers) -> ::Result<Response> {
+        let mut res = try!(self.reqwest_client.get(url)
+                           .headers(headers)
+                           .send());
+
+        let mut buf = String::new();
+        try!(res.read_to_string(&mut buf));
+
+        Ok(Response {
+            status: *res.status(),
+            headers: res.headers().clone(),
+            body: buf
+        })
+    }
+}
+
+/// A `Transport` that serves canned responses keyed by url. Requests to an
+/// unknown url get a 404 Not Found.
+///
+/// Clones share the same responses, so keep a clone around to change what
+/// the server "returns" after handing the transport to a `Client`.
+#[derive(Clone, Debug)]
+pub struct MemoryTransport {
+    responses: Arc<Mutex<HashMap<String, Response>>>,
+    requests: Arc<Mutex<Vec<(String, Headers)>>>
+}
+
+impl MemoryTransport {
+    /// Creates a new `MemoryTransport` with no responses.
+    pub fn new() -> MemoryTransport {
+        MemoryTransport {
+            responses: Arc::new(Mutex::new(HashMap::new())),
+            requests: Arc::new(Mutex::new(Vec::new()))
+        }
+    }
+
+    /// Serves `response` for every following request to `url`.
+    pub fn insert(&self, url: &str, response: Response) {
+        self.responses.lock().unwrap().insert(url.to_string(), response);
+    }
+
+    /// Serves a 200 OK with `body` for every following request to `url`.
+    pub fn insert_ok(&self, url: &str, body: &str) {
+        self.insert(url, Response::new(StatusCode::Ok, body));
+    }
+
+    /// Stops serving a response for `url`.
+    pub fn remove(&self, url: &str) {
+        self.responses.lock().unwrap().remove(url);
+    }
+
+    /// Every request made so far, in order, with the headers it was sent
+    /// with.
+    pub fn requests(&self) -> Vec<(String, Headers)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn get(&self, url: &str, headers: Headers) -> ::Result<Response> {
+        self.requests.lock().unwrap().push((url.to_string(), headers));
+
+        match self.responses.lock().unwrap().get(url) {
+            Some(res) => Ok(res.clone()),
+            None => Ok(Response::new(StatusCode::NotFound, ""))
+        }
+    }
+}
+
+/// A single request and the response it got, as stored in a fixture
+/// directory by `RecordingTransport`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Exchange {
+    pub url: String,
+    pub request_headers: Vec<(String, String)>,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    // Unix timestamp of when the response was received.
+    pub timestamp: i64
+}
+
+impl Exchange {
+    /// The recorded response.
+    pub fn response(&self) -> Response {
+        Response {
+            status: StatusCode::from_u16(self.status),
+            headers: headers_from_pairs(&self.headers),
+            body: self.body.clone()
+        }
+    }
+}
+
+/// A `Transport` that passes every request through to another `Transport`
+/// and writes each exchange to a fixture directory as a numbered json file.
+/// Play them back with `ReplayTransport`.
+#[derive(Debug)]
+pub struct RecordingTransport {
+    inner: Box<Transport>,
+    dir: PathBuf,
+    recorded: Mutex<usize>
+}
+
+impl RecordingTransport {
+    /// Creates a new `RecordingTransport`, creating `dir` if it doesn't
+    /// exist. Numbering starts from zero, so record into an empty directory
+    /// or older fixtures will be overwritten or replayed afterwards.
+    pub fn new<P: AsRef<Path>>(inner: Box<Transport>, dir: P)
+        -> ::Result<RecordingTransport> {
+        try!(fs::create_dir_all(dir.as_ref()));
+
+        Ok(RecordingTransport {
+            inner: inner,
+            dir: dir.as_ref().to_path_buf(),
+            recorded: Mutex::new(0)
+        })
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn get(&self, url: &str, headers: Headers) -> ::Result<Response> {
+        let request_headers = header_pairs(&headers);
+        let res = try!(self.inner.get(url, headers));
+
+        let exchange = Exchange {
+            url: url.to_string(),
+            request_headers: request_headers,
+            status: res.status.to_u16(),
+            headers: header_pairs(&res.headers),
+            body: res.body.clone(),
+            timestamp: UTC::now().timestamp()
+        };
+
+        // Hold the lock while writing so exchanges are numbered in the order
+        // they are written.
+        let mut recorded = self.recorded.lock().unwrap();
+        let path = self.dir.join(format!("{:05}.json", *recorded));
+        let mut file = try!(File::create(&path));
+        try!(file.write_all(
+            try!(::serde_json::to_string_pretty(&exchange)).as_bytes()));
+        *recorded += 1;
+
+        debug!("Recorded exchange for {} to {:?}", url, path);
+
+        Ok(res)
+    }
+}
+
+/// A `Transport` that serves the exchanges in a fixture directory written
+/// by `RecordingTransport`, in the order they were recorded. Requests must
+/// come in the same order as when recording.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    exchanges: Mutex<VecDeque<Exchange>>
+}
+
+impl ReplayTransport {
+    /// Creates a new `ReplayTransport` from the fixtures in `dir`.
+    pub fn new<P: AsRef<Path>>(dir: P) -> ::Result<ReplayTransport> {
+        let mut paths = Vec::new();
+        for entry in try!(fs::read_dir(dir.as_ref())) {
+            let path = try!(entry).path();
+            if path.extension().map_or(false, |ext| ext == "json") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut exchanges = VecDeque::new();
+        for path in paths {
+            let mut buf = String::new();
+            try!(try!(File::open(&path)).read_to_string(&mut buf));
+            exchanges.push_back(try!(::serde_json::from_str(&buf)));
+        }
+
+        Ok(ReplayTransport { exchanges: Mutex::new(exchanges) })
+    }
+
+    /// The number of exchanges that have not been served yet.
+    pub fn remaining(&self) -> usize {
+        self.exchanges.lock().unwrap().len()
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn get(&self, url: &str, _headers: Headers) -> ::Result<Response> {
+        let mut exchanges = self.exchanges.lock().unwrap();
+
+        let matches = match exchanges.front() {
+            Some(exchange) => exchange.url == url,
+            None => return Err(::Error::Replay(
+                format!("no recorded exchange left for {}", url)))
+        };
+        if !matches {
+            return Err(::Error::Replay(
+                format!("expected a request to {}, got {}",
+                        exchanges.front().unwrap().url, url)))
+        }
+
+        Ok(exchanges.pop_front().unwrap().response())
+    }
+}
+
+pub fn header_pairs(headers: &Headers) -> Vec<(String, String)> {
+    headers.iter()
+        .map(|h| (h.name().to_string(), h.value_string()))
+        .collect()
+}
+
+fn headers_from_pairs(pairs: &[(String, String)]) -> Headers {
+    let mut headers = Headers::new();
+    for &(ref name, ref value) in pairs {
+        headers.set_raw(name.clone(), vec![value.clone().into_bytes()]);
+    }
+    headers
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::StatusCode;
+    use reqwest::header::Headers;
+
+    use Transport;
+    use testing;
+
+    #[test]
+    fn memory_transport_serves_by_url() {
+        let transport = ::MemoryTransport::new();
+        transport.insert_ok("http://test/a.json", "a");
+
+        let res = transport.get("http://test/a.json", Headers::new()).unwrap();
+        assert_eq!(StatusCode::Ok, res.status);
+        assert_eq!("a", res.body);
+
+        let res = transport.get("http://test/b.json", Headers::new()).unwrap();
+        assert_eq!(StatusCode::NotFound, res.status);
+
+        assert_eq!(2, transport.requests().len());
+    }
+
+    #[test]
+    fn record_then_replay() {
+        let tmp = testing::TempDir::new("record-then-replay");
+        let dir = tmp.path();
+
+        let memory = ::MemoryTransport::new();
+        memory.insert_ok("http://test/a.json", "a");
+        let recorder = ::RecordingTransport::new(
+            Box::new(memory), &dir).unwrap();
+        recorder.get("http://test/a.json", Headers::new()).unwrap();
+        recorder.get("http://test/b.json", Headers::new()).unwrap();
+
+        let replay = ::ReplayTransport::new(&dir).unwrap();
+        assert_eq!(2, replay.remaining());
+        assert!(replay.get("http://test/b.json", Headers::new()).is_err());
+
+        let res = replay.get("http://test/a.json", Headers::new()).unwrap();
+        assert_eq!(StatusCode::Ok, res.status);
+        assert_eq!("a", res.body);
+        let res = replay.get("http://test/b.json", Headers::new()).unwrap();
+        assert_eq!(StatusCode::NotFound, res.status);
+        assert!(replay.get("http://test/a.json", Headers::new()).is_err());
+    }
+}
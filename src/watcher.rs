@@ -0,0 +1,391 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The default slowest a stale thread is updated.
+pub const MAX_THREAD_INTERVAL: u64 = 300;
+/// The default interval between catalog polls of a `BoardWatcher`.
+pub const CATALOG_INTERVAL: u64 = 30;
+
+/// A `WatchEvent` is sent by a `ThreadWatcher` whenever something happens to
+/// a watched thread.
+#[derive(Debug)]
+pub enum WatchEvent {
+    // The thread changed. Contains the updated thread and what changed.
+    // Threads that expired or were archived are unwatched after this.
+    Updated(::Thread, ::ThreadDelta),
+    // Updating the thread with this number failed. It stays watched.
+    Failed(u64, ::Error)
+}
+
+enum Command {
+    Watch(::Thread),
+    Unwatch(u64),
+    Stop
+}
+
+/// A `ThreadWatcher` updates threads in the background and sends a
+/// `WatchEvent` for every change.
+///
+/// Each thread is first updated right away and then every 10 seconds. While
+/// a thread stays the same the interval doubles, up to a maximum, and it
+/// snaps back as soon as new replies arrive. Threads that expire or are
+/// archived are unwatched.
+///
+/// The watcher updates its own copies of the threads, so a `Board`'s
+/// `ThreadCache` doesn't see their updates; pin a watched thread there if
+/// it must stay cached, and replace it from the `WatchEvent`s. Threads from
+/// a cache backed by a `Storage` still upsert every update into it.
+#[derive(Debug)]
+pub struct ThreadWatcher {
+    commands: Sender<Command>,
+    handle: Option<JoinHandle<()>>
+}
+
+impl ThreadWatcher {
+    /// Creates a new `ThreadWatcher` with the default intervals. Returns the
+    /// watcher and the receiving end of its events.
+    pub fn new() -> (ThreadWatcher, Receiver<WatchEvent>) {
+        ThreadWatcher::with_intervals(
+            Duration::from_secs(::thread::THREAD_INTERVAL_SECS),
+            Duration::from_secs(MAX_THREAD_INTERVAL))
+    }
+
+    /// Creates a new `ThreadWatcher` updating threads no faster than `min`
+    /// and no slower than `max`. Don't go below 10 seconds outside of tests.
+    pub fn with_intervals(min: Duration, max: Duration)
+        -> (ThreadWatcher, Receiver<WatchEvent>) {
+        let (command_tx, command_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            run(command_rx, event_tx, min, cmp::max(min, max));
+        });
+
+        (ThreadWatcher { commands: command_tx, handle: Some(handle) }, event_rx)
+    }
+
+    /// Starts watching a thread. A thread with the same number that is
+    /// already watched is replaced.
+    pub fn watch(&self, thread: ::Thread) {
+        let _ = self.commands.send(Command::Watch(thread));
+    }
+
+    /// Stops watching the thread with this number.
+    pub fn unwatch(&self, thread_no: u64) {
+        let _ = self.commands.send(Command::Unwatch(thread_no));
+    }
+}
+
+impl Drop for ThreadWatcher {
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Stop);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+struct Watched {
+    thread: ::Thread,
+    interval: Duration,
+    due: Instant
+}
+
+impl Watched {
+    /// Schedules the next update depending on what the last one brought.
+    fn reschedule(&mut self, delta: Option<&::ThreadDelta>,
+                  min: Duration, max: Duration) {
+        self.interval = match delta {
+            Some(delta) if !delta.new_replies.is_empty() => min,
+            _ => cmp::min(self.interval * 2, max)
+        };
+        self.due = Instant::now() + self.interval;
+    }
+}
+
+fn run(commands: Receiver<Command>, events: Sender<WatchEvent>,
+       min: Duration, max: Duration) {
+    let mut watched: HashMap<u64, Watched> = HashMap::new();
+
+    loop {
+        let now = Instant::now();
+        let next_due = watched.values().map(|w| w.due).min();
+        let command = match next_due {
+            Some(due) if due <= now => Err(RecvTimeoutError::Timeout),
+            Some(due) => commands.recv_timeout(due - now),
+            None => commands.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+
+        match command {
+            Ok(Command::Watch(thread)) => {
+                let thread_no = thread.topic.no;
+                watched.insert(thread_no, Watched {
+                    thread: thread,
+                    interval: min,
+                    due: Instant::now()
+                });
+                continue
+            },
+            Ok(Command::Unwatch(thread_no)) => {
+                watched.remove(&thread_no);
+                continue
+            },
+            Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => ()
+        }
+
+        let now = Instant::now();
+        let due = watched.iter()
+            .filter(|&(_, w)| w.due <= now)
+            .map(|(&no, _)| no)
+            .collect::<Vec<u64>>();
+
+        for thread_no in due {
+            let finished = {
+                let w = watched.get_mut(&thread_no).unwrap();
+                match w.thread.update() {
+                    Ok(delta) => {
+                        w.reschedule(Some(&delta), min, max);
+                        let finished = delta.is_final() ||
+                            w.thread.is_expired() || w.thread.is_archived();
+                        if !delta.is_empty() {
+                            let event = WatchEvent::Updated(
+                                w.thread.clone(), delta);
+                            if events.send(event).is_err() {
+                                // Nobody is listening anymore.
+                                return
+                            }
+                        }
+                        finished
+                    },
+                    Err(e) => {
+                        debug!("Failed to update thread {}: {}", thread_no, e);
+                        w.reschedule(None, min, max);
+                        if events.send(WatchEvent::Failed(thread_no, e))
+                            .is_err() {
+                            return
+                        }
+                        false
+                    }
+                }
+            };
+            if finished {
+                watched.remove(&thread_no);
+            }
+        }
+    }
+}
+
+/// A `BoardEvent` is sent by a `BoardWatcher` for every change it sees
+/// between two catalogs.
+#[derive(Debug)]
+pub enum BoardEvent {
+    // A thread that wasn't in the last catalog. Contains its topic.
+    NewThread(::Post),
+    // The thread with this number is no longer in the catalog.
+    ThreadRemoved(u64),
+    // A thread got new replies or was otherwise modified. Contains its
+    // updated topic.
+    Bumped(::Post),
+    // A thread moved from one page of the catalog to another.
+    PageMoved { no: u64, from: u8, to: u8 },
+    // Polling the catalog failed. The watcher keeps polling.
+    Failed(::Error)
+}
+
+/// A `BoardWatcher` polls a board's catalog in the background, using
+/// If-Modified-Since, and sends a `BoardEvent` for every change. The board's
+/// thread cache is kept up to date as with `Board::catalog`.
+///
+/// Every thread in the first catalog is reported as a `NewThread`.
+#[derive(Debug)]
+pub struct BoardWatcher {
+    stop: Sender<()>,
+    handle: Option<JoinHandle<()>>
+}
+
+impl BoardWatcher {
+    /// Creates a new `BoardWatcher` polling every 30 seconds. Returns the
+    /// watcher and the receiving end of its events.
+    pub fn new(board: ::Board) -> (BoardWatcher, Receiver<BoardEvent>) {
+        BoardWatcher::with_interval(board,
+                                    Duration::from_secs(CATALOG_INTERVAL))
+    }
+
+    /// Creates a new `BoardWatcher` polling every `interval`. Don't go below
+    /// 10 seconds outside of tests.
+    pub fn with_interval(board: ::Board, interval: Duration)
+        -> (BoardWatcher, Receiver<BoardEvent>) {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let mut snapshot = HashMap::new();
+            loop {
+                let events = match board.catalog() {
+                    Ok(Some(catalog)) => {
+                        let (events, next) = diff_catalog(&snapshot, &catalog);
+                        snapshot = next;
+                        events
+                    },
+                    Ok(None) => Vec::new(),
+                    Err(e) => vec![BoardEvent::Failed(e)]
+                };
+                for event in events {
+                    if event_tx.send(event).is_err() {
+                        return
+                    }
+                }
+
+                match stop_rx.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => (),
+                    _ => return
+                }
+            }
+        });
+
+        (BoardWatcher { stop: stop_tx, handle: Some(handle) }, event_rx)
+    }
+}
+
+impl Drop for BoardWatcher {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The page and topic of every thread in a catalog.
+type Snapshot = HashMap<u64, (u8, ::Post)>;
+
+/// The events between the last catalog, as a snapshot, and a new one.
+/// Returns the events and the snapshot of the new catalog.
+fn diff_catalog(old: &Snapshot, catalog: &::Catalog)
+    -> (Vec<BoardEvent>, Snapshot) {
+    let mut events = Vec::new();
+    let mut new = Snapshot::new();
+
+    for page in &catalog.pages {
+        for topic in &page.topics {
+            match old.get(&topic.no) {
+                None => events.push(BoardEvent::NewThread(topic.clone())),
+                Some(&(old_page, ref old_topic)) => {
+                    if topic.last_modified > old_topic.last_modified ||
+                        topic.replies != old_topic.replies {
+                        events.push(BoardEvent::Bumped(topic.clone()));
+                    }
+                    if page.page != old_page {
+                        events.push(BoardEvent::PageMoved {
+                            no: topic.no,
+                            from: old_page,
+                            to: page.page
+                        });
+                    }
+                }
+            }
+            new.insert(topic.no, (page.page, topic.clone()));
+        }
+    }
+
+    let mut removed = old.keys()
+        .filter(|no| !new.contains_key(no))
+        .cloned()
+        .collect::<Vec<u64>>();
+    removed.sort();
+    events.extend(removed.into_iter().map(BoardEvent::ThreadRemoved));
+
+    (events, new)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use testing;
+
+    #[test]
+    fn watcher_sends_updates() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+        let thread = g.get_thread(100).unwrap();
+
+        let (watcher, events) = ::ThreadWatcher::with_intervals(
+            Duration::from_millis(10), Duration::from_millis(40));
+        transport.insert_ok(testing::THREAD_URL, r#"{"posts": [
+            {"no": 100, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+             "time": 1492218205, "replies": 2},
+            {"no": 101, "resto": 100, "now": "04/15/17(Sat)01:05:00",
+             "time": 1492218300},
+            {"no": 102, "resto": 100, "now": "04/15/17(Sat)01:05:10",
+             "time": 1492218310}
+        ]}"#);
+        watcher.watch(thread);
+
+        match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+            ::WatchEvent::Updated(thread, delta) => {
+                assert_eq!(2, thread.replies.len());
+                assert_eq!(102, delta.new_replies[0].no);
+            },
+            event => panic!("unexpected event: {:?}", event)
+        }
+
+        transport.remove(testing::THREAD_URL);
+        match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+            ::WatchEvent::Updated(thread, delta) => {
+                assert!(thread.is_expired());
+                assert!(delta.is_final());
+            },
+            event => panic!("unexpected event: {:?}", event)
+        }
+    }
+
+    #[test]
+    fn board_watcher_sends_catalog_changes() {
+        let transport = testing::transport();
+        let g = ::Board::new(testing::client(&transport), "g").unwrap();
+
+        let (watcher, events) = ::BoardWatcher::with_interval(
+            g.clone(), Duration::from_millis(10));
+        let mut new_threads = Vec::new();
+        for _ in 0..2 {
+            match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+                ::BoardEvent::NewThread(topic) => new_threads.push(topic.no),
+                event => panic!("unexpected event: {:?}", event)
+            }
+        }
+        assert_eq!(vec![100, 200], new_threads);
+
+        transport.insert_ok(testing::CATALOG_URL, r#"[
+            {"page": 1, "threads": [
+                {"no": 200, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+                 "time": 1492218205, "replies": 1,
+                 "last_modified": 1492218400}
+            ]},
+            {"page": 2, "threads": [
+                {"no": 100, "resto": 0, "now": "04/15/17(Sat)01:03:25",
+                 "time": 1492218205, "replies": 1,
+                 "last_modified": 1492218205}
+            ]}
+        ]"#);
+
+        let mut seen = Vec::new();
+        for _ in 0..2 {
+            match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+                ::BoardEvent::Bumped(topic) => seen.push(("bumped", topic.no)),
+                ::BoardEvent::PageMoved { no, from, to } => {
+                    assert_eq!((1, 2), (from, to));
+                    seen.push(("moved", no));
+                },
+                event => panic!("unexpected event: {:?}", event)
+            }
+        }
+        assert_eq!(vec![("bumped", 200), ("moved", 100)], seen);
+        drop(watcher);
+        assert!(g.thread_cache.lock().unwrap().contains(200));
+    }
+}